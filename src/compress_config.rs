@@ -1,47 +1,748 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, collections::BTreeMap, ops::Range, rc::Rc, str::FromStr};
 
-use anyhow::Result;
+use anyhow::{anyhow, ensure, Context, Result};
 use serde::{Deserialize, Serialize};
 
-use crate::model::{
-    AdaptiveProbabilityMap, HashTable, LnMixerPred, Model, NOrderByte, NOrderByteData,
+use crate::{
+    filter::Filter,
+    model::{
+        AdaptiveProbabilityMap, ApmStage, Audio, BitHistoryCounters, Column, Float32, GlslToken,
+        Gru, HashTable, Image, InRange, Indirect, JsLexerModel, LnMixerPred, Model, NOrderByte,
+        NOrderByteData, Number, Record, MAX_FLOAT32_STRIDE, MAX_GRU_CELLS, MAX_RECORD_LENGTH,
+    },
+    model_finder::create_default_model_config,
 };
 
-#[cfg(test)]
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CompressConfig {
+    #[serde(default = "create_default_model_config")]
     pub model: ModelConfig,
+    #[serde(default)]
+    pub hash_table_size: HashTableSize,
+    /// Verify hash table entries with a check byte instead of sharing them on collisions
+    #[serde(default)]
+    pub checked_hash_table: bool,
+    /// Warm up the model with the minified decompressor source before compressing
+    #[serde(default)]
+    pub prime_decompressor: bool,
+    /// Text file to warm up the model with after the decompressor source.
+    /// It is embedded into the bootstrap.
+    #[serde(default)]
+    pub prime_with: Option<String>,
+    /// Minify the .glsl, .frag and .vert files before compressing
+    #[serde(default)]
+    pub minify_glsl: bool,
+    /// Decode the .png files to RGBA pixels before compressing
+    #[serde(default)]
+    pub decode_png: bool,
+    /// Decode the .wav files to planar PCM samples before compressing
+    #[serde(default)]
+    pub decode_wav: bool,
+    /// Store every file whichever way is smallest: with the model, deflated or as it is
+    #[serde(default)]
+    pub auto: bool,
+    /// Reorder the files to the order that compresses best
+    #[serde(default)]
+    pub optimize_order: bool,
+    /// Rename the locals of the JS main for the given number of most frequent locals
+    #[serde(default)]
+    pub rename_js: Option<usize>,
+    /// Text files (base names) to replace frequent long words in before compressing
+    #[serde(default)]
+    pub word_replace: Vec<String>,
+    /// Reversible filters applied in order to files (base names) before compressing
+    #[serde(default)]
+    pub filters: BTreeMap<String, Vec<Filter>>,
+    /// Additional hash tables that models can be assigned to by name
+    #[serde(default)]
+    pub hash_tables: BTreeMap<String, HashTableConfig>,
 }
 
+impl Default for CompressConfig {
+    fn default() -> Self {
+        Self {
+            model: create_default_model_config(),
+            hash_table_size: HashTableSize::default(),
+            checked_hash_table: false,
+            prime_decompressor: false,
+            prime_with: None,
+            minify_glsl: false,
+            decode_png: false,
+            decode_wav: false,
+            auto: false,
+            optimize_order: false,
+            rename_js: None,
+            word_replace: Vec::new(),
+            filters: BTreeMap::new(),
+            hash_tables: BTreeMap::new(),
+        }
+    }
+}
+
+impl CompressConfig {
+    /// Resolves the size of every hash table used by the model.
+    /// The shared hash table has no name.
+    pub fn hash_table_layouts(&self, input_len: usize) -> Result<HashTableLayouts> {
+        let mut users = BTreeMap::new();
+        self.model.hash_table_users(&mut users);
+
+        users
+            .into_iter()
+            .map(|(name, users)| {
+                let (size, checked) = match &name {
+                    None => (self.hash_table_size, self.checked_hash_table),
+                    Some(name) => {
+                        let config = self
+                            .hash_tables
+                            .get(name)
+                            .context(format!("Unknown hash table: {}", name))?;
+                        (config.size, config.checked)
+                    }
+                };
+
+                let layout = HashTableLayout {
+                    pow2_size: size.pow2_size(input_len, users)?,
+                    checked,
+                };
+                ensure!(
+                    layout.bytes() <= HashTableSize::MAX_BYTES,
+                    "Hash table '{}' takes {} MiB, more than the {} MiB browsers allocate",
+                    name.as_deref().unwrap_or("shared"),
+                    layout.bytes() >> 20,
+                    HashTableSize::MAX_BYTES >> 20
+                );
+                Ok((name, layout))
+            })
+            .collect()
+    }
+}
+
+/// A hash table that NOrderByte and word models can be assigned to by name
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HashTableConfig {
+    #[serde(default)]
+    pub size: HashTableSize,
+    #[serde(default)]
+    pub checked: bool,
+}
+
+/// Hash table with its size resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashTableLayout {
+    pub pow2_size: u32,
+    pub checked: bool,
+}
+
+impl HashTableLayout {
+    /// Memory the decoder allocates for the table, 4 bytes per entry
+    /// plus a check byte per entry in a separate array if it's checked
+    pub fn bytes(&self) -> u64 {
+        (4 + self.checked as u64) << self.pow2_size
+    }
+
+    pub fn create(&self) -> SharedHashTable {
+        Rc::new(RefCell::new(if self.checked {
+            HashTable::<NOrderByteData>::new_checked(self.pow2_size)
+        } else {
+            HashTable::<NOrderByteData>::new(self.pow2_size)
+        }))
+    }
+}
+
+/// Hash tables by name, `None` is the shared hash table
+pub type HashTableLayouts = BTreeMap<Option<String>, HashTableLayout>;
+
+pub type SharedHashTable = Rc<RefCell<HashTable<NOrderByteData>>>;
+pub type HashTables = BTreeMap<Option<String>, SharedHashTable>;
+
+/// Byte range of every input file in the compressed stream, by file name
+pub type FileRanges = BTreeMap<String, Range<u32>>;
+
+pub fn create_hash_tables(layouts: &HashTableLayouts) -> HashTables {
+    layouts
+        .iter()
+        .map(|(name, layout)| (name.clone(), layout.create()))
+        .collect()
+}
+
+/// Size of a hash table used by the NOrderByte and word models
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashTableSize {
+    /// Derived from the input length and the number of models using the table
+    #[default]
+    Auto,
+    /// Log2 of the number of entries
+    #[serde(untagged)]
+    Pow2(u32),
+}
+
+impl HashTableSize {
+    pub const MIN_POW2: u32 = 16;
+    /// Upper bound of the auto mode, to keep the memory usage in the browser reasonable
+    pub const MAX_AUTO_POW2: u32 = 26;
+    /// Largest ArrayBuffer all major browsers allocate
+    pub const MAX_BYTES: u64 = 1 << 31;
+    /// Entries of 4 bytes that fit in `MAX_BYTES`, checked tables need less
+    pub const MAX_POW2: u32 = 29;
+
+    pub fn pow2_size(&self, input_len: usize, users: usize) -> Result<u32> {
+        Ok(match *self {
+            HashTableSize::Auto => {
+                // Every model touches at most one entry per bit
+                let max_entries = (input_len as u64 * 8 * users as u64).next_power_of_two();
+                max_entries
+                    .trailing_zeros()
+                    .clamp(Self::MIN_POW2, Self::MAX_AUTO_POW2)
+            }
+            HashTableSize::Pow2(pow2_size) => {
+                ensure!(
+                    (Self::MIN_POW2..=Self::MAX_POW2).contains(&pow2_size),
+                    "Hash table size must be in [{}, {}]",
+                    Self::MIN_POW2,
+                    Self::MAX_POW2
+                );
+                pow2_size
+            }
+        })
+    }
+}
+
+impl FromStr for HashTableSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(HashTableSize::Auto);
+        }
+
+        s.parse::<u32>()
+            .map(HashTableSize::Pow2)
+            .map_err(|_| anyhow!("Expected 'auto' or the log2 of the number of entries"))
+    }
+}
+
+/// What the hash table slots of a model store
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CounterType {
+    /// A probability averaging over the whole history of the context
+    #[default]
+    Probability,
+    /// A bit history state mapped to an adaptive probability,
+    /// which reacts faster to changes in the context
+    BitHistory,
+}
+
+impl CounterType {
+    fn is_default(&self) -> bool {
+        *self == CounterType::default()
+    }
+}
+
+/// Configuration of a single APM stage
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApmConfig {
+    /// Log2 of the number of contexts in the APM table
+    #[serde(default = "ApmConfig::default_pow2_size")]
+    pub pow2_size: u32,
+    /// Number of previous bytes used as context [0, 8]
+    #[serde(default = "ApmConfig::default_order")]
+    pub order: u32,
+    /// Count offset of the learning function, higher values adapt slower
+    #[serde(default = "ApmConfig::default_rate")]
+    pub rate: u32,
+}
+
+impl ApmConfig {
+    /// Every context takes 32 counters of 4 bytes, so this is 512 MiB,
+    /// which the decoder allocates as well
+    pub const MAX_POW2: u32 = 22;
+
+    fn default_pow2_size() -> u32 {
+        19
+    }
+
+    fn default_order() -> u32 {
+        3
+    }
+
+    fn default_rate() -> u32 {
+        30
+    }
+}
+
+impl Default for ApmConfig {
+    fn default() -> Self {
+        Self {
+            pow2_size: Self::default_pow2_size(),
+            order: Self::default_order(),
+            rate: Self::default_rate(),
+        }
+    }
+}
+
+/// A model or a combination of models, selected by `type`.
+/// Models that keep statistics in hash table slots share two fields:
+/// `table` names the hash table to use, the shared one if not set,
+/// and `counter` is what the slots store (see `CounterType`).
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum ModelConfig {
-    NOrderByte { byte_mask: String },
-    Mixer { models: Vec<ModelConfig> },
-    AdaptiveProbabilityMap(Box<ModelConfig>),
-    Word,
+    NOrderByte {
+        byte_mask: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        table: Option<String>,
+        #[serde(default, skip_serializing_if = "CounterType::is_default")]
+        counter: CounterType,
+    },
+    Mixer {
+        models: Vec<ModelConfig>,
+    },
+    /// Refines the prediction of `model` with the given APM stages.
+    /// The outputs of the stages are averaged, nest APMs to chain them.
+    AdaptiveProbabilityMap {
+        model: Box<ModelConfig>,
+        #[serde(default = "ModelConfig::default_apm_stages")]
+        stages: Vec<ApmConfig>,
+    },
+    Word {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        table: Option<String>,
+        #[serde(default, skip_serializing_if = "CounterType::is_default")]
+        counter: CounterType,
+    },
+    /// Predicts from the bytes that followed the last occurrences of the
+    /// order-1 or order-2 context, instead of the context itself
+    Indirect {
+        #[serde(default = "ModelConfig::default_indirect_order")]
+        order: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        table: Option<String>,
+        #[serde(default, skip_serializing_if = "CounterType::is_default")]
+        counter: CounterType,
+    },
+    /// Uses the bytes at the same column of the previous records of
+    /// fixed-width binary data and the column as context
+    Record {
+        /// Record length in bytes, detected from the data of each file if not set
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        length: Option<u32>,
+        /// Number of previous records used as context, 1 or 2
+        #[serde(default = "ModelConfig::default_record_records")]
+        records: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        table: Option<String>,
+        #[serde(default, skip_serializing_if = "CounterType::is_default")]
+        counter: CounterType,
+        /// Start positions of the files, where length detection starts over.
+        /// Set by `resolve_files`
+        #[serde(skip)]
+        file_starts: Vec<u32>,
+    },
+    /// Predicts raw pixel data from the neighbouring pixels of the same channel
+    Image {
+        /// Width in pixels
+        width: u32,
+        #[serde(default = "ModelConfig::default_channels")]
+        channels: u32,
+        #[serde(default = "ModelConfig::default_bytes_per_channel")]
+        bytes_per_channel: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        table: Option<String>,
+        #[serde(default, skip_serializing_if = "CounterType::is_default")]
+        counter: CounterType,
+    },
+    /// Predicts PCM samples from linear predictions of the previous samples.
+    /// 8 bit samples are unsigned, 16 bit samples signed little-endian.
+    Audio {
+        #[serde(default = "ModelConfig::default_bits_per_sample")]
+        bits_per_sample: u32,
+        #[serde(default = "ModelConfig::default_channels")]
+        channels: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        table: Option<String>,
+        #[serde(default, skip_serializing_if = "CounterType::is_default")]
+        counter: CounterType,
+    },
+    /// Uses the character above in the previous line and the column as context
+    Column {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        table: Option<String>,
+        #[serde(default, skip_serializing_if = "CounterType::is_default")]
+        counter: CounterType,
+    },
+    /// Uses the indentation depth and first character of the line
+    /// with the last character as context
+    Indentation {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        table: Option<String>,
+        #[serde(default, skip_serializing_if = "CounterType::is_default")]
+        counter: CounterType,
+    },
+    /// Mixes the state of a JavaScript lexer (literal or comment, bracket depth
+    /// and previous token class) with the last `order` bytes [0, 4]
+    JsLexer {
+        #[serde(default = "ModelConfig::default_js_lexer_order")]
+        order: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        table: Option<String>,
+        #[serde(default, skip_serializing_if = "CounterType::is_default")]
+        counter: CounterType,
+    },
+    /// Word model with a GLSL tokenizer, using the previous two tokens
+    /// and the current token as context
+    GlslToken {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        table: Option<String>,
+        #[serde(default, skip_serializing_if = "CounterType::is_default")]
+        counter: CounterType,
+    },
+    /// Predicts numeric literals in text from the digit position, decimal point
+    /// and the shape of the previous number
+    Number {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        table: Option<String>,
+        #[serde(default, skip_serializing_if = "CounterType::is_default")]
+        counter: CounterType,
+    },
+    /// Predicts little-endian float32 arrays from the previous elements
+    Float32 {
+        /// Number of floats per element, e.g. 3 for xyz positions
+        #[serde(default = "ModelConfig::default_stride")]
+        stride: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        table: Option<String>,
+        #[serde(default, skip_serializing_if = "CounterType::is_default")]
+        counter: CounterType,
+    },
+    /// Small recurrent network over the previous bytes that learns online,
+    /// slow but helps with text
+    Gru {
+        /// Number of hidden cells
+        #[serde(default = "ModelConfig::default_gru_cells")]
+        cells: u32,
+    },
+    /// Applies `model` only to the bytes of the input file with the given name,
+    /// as if the file was the whole input
+    InFile {
+        file: String,
+        model: Box<ModelConfig>,
+        /// Set by `resolve_files`
+        #[serde(skip)]
+        range: Option<Range<u32>>,
+    },
 }
 
 impl ModelConfig {
-    pub fn create_model(
-        &self,
-        hash_table: Rc<RefCell<HashTable<NOrderByteData>>>,
-    ) -> Result<Box<dyn Model>> {
+    fn default_apm_stages() -> Vec<ApmConfig> {
+        vec![ApmConfig::default()]
+    }
+
+    fn default_indirect_order() -> u32 {
+        1
+    }
+
+    fn default_record_records() -> u32 {
+        2
+    }
+
+    fn default_channels() -> u32 {
+        1
+    }
+
+    fn default_bytes_per_channel() -> u32 {
+        1
+    }
+
+    fn default_bits_per_sample() -> u32 {
+        16
+    }
+
+    fn default_stride() -> u32 {
+        1
+    }
+
+    fn default_js_lexer_order() -> u32 {
+        1
+    }
+
+    fn default_gru_cells() -> u32 {
+        32
+    }
+
+    /// Looks up the byte ranges of the files `InFile` models apply to
+    pub fn resolve_files(&mut self, files: &FileRanges) -> Result<()> {
+        match self {
+            ModelConfig::Mixer { models } => {
+                for model in models {
+                    model.resolve_files(files)?;
+                }
+            }
+            ModelConfig::AdaptiveProbabilityMap { model, .. } => model.resolve_files(files)?,
+            ModelConfig::InFile { file, model, range } => {
+                let file_range = files
+                    .get(file)
+                    .cloned()
+                    .context(format!("File '{}' is not part of the input", file))?;
+                // The model only sees the bytes of the file
+                let clamp =
+                    |pos: u32| pos.clamp(file_range.start, file_range.end) - file_range.start;
+                let files_in_range = files
+                    .iter()
+                    .map(|(name, range)| (name.clone(), clamp(range.start)..clamp(range.end)))
+                    .collect();
+                model.resolve_files(&files_in_range)?;
+                *range = Some(file_range);
+            }
+            ModelConfig::Record { file_starts, .. } => {
+                *file_starts = files.values().map(|range| range.start).collect();
+                file_starts.sort_unstable();
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Adds `model` to the mixer, or mixes it with the model if there's no mixer
+    pub fn add_to_mixer(&mut self, model: ModelConfig) {
+        match self {
+            ModelConfig::Mixer { models } => models.push(model),
+            ModelConfig::AdaptiveProbabilityMap { model: input, .. } => input.add_to_mixer(model),
+            _ => {
+                let current = std::mem::replace(self, ModelConfig::Mixer { models: Vec::new() });
+                *self = ModelConfig::Mixer {
+                    models: vec![current, model],
+                };
+            }
+        }
+    }
+
+    /// Names of the files `InFile` models apply to
+    pub fn in_files(&self) -> Vec<&str> {
+        match self {
+            ModelConfig::Mixer { models } => {
+                models.iter().flat_map(ModelConfig::in_files).collect()
+            }
+            ModelConfig::AdaptiveProbabilityMap { model, .. } => model.in_files(),
+            ModelConfig::InFile { file, model, .. } => {
+                let mut files = model.in_files();
+                files.push(file);
+                files
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Counts the models using each hash table
+    pub fn hash_table_users(&self, users: &mut BTreeMap<Option<String>, usize>) {
+        match self {
+            ModelConfig::NOrderByte { table, .. }
+            | ModelConfig::Word { table, .. }
+            | ModelConfig::Indirect { table, .. }
+            | ModelConfig::Record { table, .. }
+            | ModelConfig::Image { table, .. }
+            | ModelConfig::Audio { table, .. }
+            | ModelConfig::Float32 { table, .. }
+            | ModelConfig::Column { table, .. }
+            | ModelConfig::Indentation { table, .. }
+            | ModelConfig::JsLexer { table, .. }
+            | ModelConfig::GlslToken { table, .. }
+            | ModelConfig::Number { table, .. } => {
+                *users.entry(table.clone()).or_default() += 1;
+            }
+            ModelConfig::Mixer { models } => {
+                for model in models {
+                    model.hash_table_users(users);
+                }
+            }
+            ModelConfig::AdaptiveProbabilityMap { model, .. }
+            | ModelConfig::InFile { model, .. } => model.hash_table_users(users),
+            ModelConfig::Gru { .. } => {}
+        }
+    }
+
+    pub fn create_model(&self, hash_tables: &HashTables) -> Result<Box<dyn Model>> {
+        let hash_table = |table: &Option<String>| {
+            hash_tables.get(table).cloned().context(format!(
+                "Hash table '{}' was not created",
+                table.as_deref().unwrap_or_default()
+            ))
+        };
+
         Ok(match self {
-            ModelConfig::NOrderByte { byte_mask } => {
+            ModelConfig::NOrderByte {
+                byte_mask,
+                table,
+                counter,
+            } => {
                 let byte_mask = u8::from_str_radix(byte_mask.trim_start_matches("0b"), 2)?;
-                Box::new(NOrderByte::new_norder_model(byte_mask, hash_table, 255))
+                with_counter(
+                    NOrderByte::new_norder_model(byte_mask, hash_table(table)?, 255),
+                    counter,
+                )
             }
             ModelConfig::Mixer { models } => Box::new(LnMixerPred::new(
                 models
                     .iter()
-                    .map(|config| config.create_model(hash_table.clone()))
+                    .map(|config| config.create_model(hash_tables))
                     .collect::<Result<Vec<_>>>()?,
             )),
-            ModelConfig::AdaptiveProbabilityMap(model_config) => Box::new(
-                AdaptiveProbabilityMap::new(19, model_config.create_model(hash_table.clone())?),
-            ),
-            ModelConfig::Word => Box::new(NOrderByte::new_word_model(hash_table, 255)),
+            ModelConfig::AdaptiveProbabilityMap { model, stages } => {
+                ensure!(!stages.is_empty(), "APM requires at least one stage");
+                let stages = stages
+                    .iter()
+                    .map(|stage| {
+                        ensure!(stage.order <= 8, "APM order must be in [0, 8]");
+                        ensure!(
+                            (1..=ApmConfig::MAX_POW2).contains(&stage.pow2_size),
+                            "APM table size must be in [1, {}]",
+                            ApmConfig::MAX_POW2
+                        );
+                        Ok(ApmStage::new(stage.pow2_size, stage.order, stage.rate))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+
+                Box::new(AdaptiveProbabilityMap::new(
+                    stages,
+                    model.create_model(hash_tables)?,
+                ))
+            }
+            ModelConfig::Word { table, counter } => {
+                with_counter(NOrderByte::new_word_model(hash_table(table)?, 255), counter)
+            }
+            ModelConfig::Indirect {
+                order,
+                table,
+                counter,
+            } => {
+                ensure!((1..=2).contains(order), "Indirect order must be 1 or 2");
+                with_counter(Indirect::new(*order, hash_table(table)?), counter)
+            }
+            ModelConfig::Record {
+                length,
+                records,
+                table,
+                counter,
+                file_starts,
+            } => {
+                ensure!((1..=2).contains(records), "Record records must be 1 or 2");
+                if let Some(length) = length {
+                    ensure!(
+                        (1..=MAX_RECORD_LENGTH).contains(length),
+                        "Record length must be in [1, {}]",
+                        MAX_RECORD_LENGTH
+                    );
+                }
+                with_counter(
+                    Record::new(
+                        length.unwrap_or(0),
+                        *records,
+                        file_starts.clone(),
+                        hash_table(table)?,
+                    ),
+                    counter,
+                )
+            }
+            ModelConfig::Image {
+                width,
+                channels,
+                bytes_per_channel,
+                table,
+                counter,
+            } => {
+                ensure!(*width > 0, "Image width must be at least 1");
+                ensure!(
+                    (1..=16).contains(channels),
+                    "Image channels must be in [1, 16]"
+                );
+                ensure!(
+                    (1..=4).contains(bytes_per_channel),
+                    "Image bytes per channel must be in [1, 4]"
+                );
+                ensure!(
+                    2 * *width as u64 * *channels as u64 * *bytes_per_channel as u64 <= 1 << 28,
+                    "Image rows are too large"
+                );
+                with_counter(
+                    Image::new(*width, *channels, *bytes_per_channel, hash_table(table)?),
+                    counter,
+                )
+            }
+            ModelConfig::Audio {
+                bits_per_sample,
+                channels,
+                table,
+                counter,
+            } => {
+                ensure!(
+                    *bits_per_sample == 8 || *bits_per_sample == 16,
+                    "Audio bits per sample must be 8 or 16"
+                );
+                ensure!(
+                    (1..=16).contains(channels),
+                    "Audio channels must be in [1, 16]"
+                );
+                with_counter(
+                    Audio::new(*bits_per_sample, *channels, hash_table(table)?),
+                    counter,
+                )
+            }
+            ModelConfig::Float32 {
+                stride,
+                table,
+                counter,
+            } => {
+                ensure!(
+                    (1..=MAX_FLOAT32_STRIDE).contains(stride),
+                    "Float32 stride must be in [1, {}]",
+                    MAX_FLOAT32_STRIDE
+                );
+                with_counter(Float32::new(*stride, hash_table(table)?), counter)
+            }
+            ModelConfig::Column { table, counter } => {
+                with_counter(Column::new_above_model(hash_table(table)?), counter)
+            }
+            ModelConfig::Indentation { table, counter } => {
+                with_counter(Column::new_indentation_model(hash_table(table)?), counter)
+            }
+            ModelConfig::JsLexer {
+                order,
+                table,
+                counter,
+            } => {
+                ensure!(*order <= 4, "JS lexer order must be in [0, 4]");
+                with_counter(JsLexerModel::new(*order, hash_table(table)?), counter)
+            }
+            ModelConfig::GlslToken { table, counter } => {
+                with_counter(GlslToken::new(hash_table(table)?), counter)
+            }
+            ModelConfig::Number { table, counter } => {
+                with_counter(Number::new(hash_table(table)?), counter)
+            }
+            ModelConfig::Gru { cells } => {
+                ensure!(
+                    (1..=MAX_GRU_CELLS).contains(cells),
+                    "Gru cells must be in [1, {}]",
+                    MAX_GRU_CELLS
+                );
+                Box::new(Gru::new(*cells))
+            }
+            ModelConfig::InFile { file, model, range } => Box::new(InRange::new(
+                range
+                    .clone()
+                    .context(format!("File '{}' of InFile model was not resolved", file))?,
+                model.create_model(hash_tables)?,
+            )),
         })
     }
 }
+
+fn with_counter<M: BitHistoryCounters + 'static>(
+    model: M,
+    counter: &CounterType,
+) -> Box<dyn Model> {
+    match counter {
+        CounterType::Probability => Box::new(model),
+        CounterType::BitHistory => Box::new(model.with_bit_history()),
+    }
+}
@@ -48,13 +48,15 @@ impl<W: Write> Encoder<W> {
     }
 
     /// Warms up the model by reading a byte stream and learning from it.
-    #[allow(dead_code)]
+    /// The decoder has to warm up with the same bytes.
     pub fn warm_up(&mut self, mut byte_stream: impl Read) -> Result<()> {
         let mut bytes = Vec::<u8>::new();
         byte_stream.read_to_end(&mut bytes)?;
         for b in bytes {
             for i in 0..8 {
                 let bit = (b >> (7 - i)) & 1;
+                // Models and mixers learn from their last prediction
+                self.model.pred();
                 self.model.learn(bit);
             }
         }
@@ -63,6 +65,21 @@ impl<W: Write> Encoder<W> {
     }
 }
 
+/// Number of bits the model needs to code `bytes`, which is within a few bytes
+/// of what the encoder outputs, without running the arithmetic coder
+pub fn bit_cost(mut model: Box<dyn Model>, bytes: &[u8]) -> f64 {
+    let mut bits = 0.;
+    for b in bytes {
+        for i in 0..8 {
+            let prob = prob_squash(model.pred());
+            let bit = (b >> (7 - i)) & 1;
+            bits -= if bit == 1 { prob } else { 1. - prob }.log2();
+            model.learn(bit);
+        }
+    }
+    bits
+}
+
 #[cfg(test)]
 mod tests {
     use std::{fs::File, io::Read};
@@ -110,6 +127,7 @@ mod tests {
             for b in bytes {
                 for i in 0..8 {
                     let bit = (b >> (7 - i)) & 1;
+                    self.model.pred();
                     self.model.learn(bit);
                 }
             }
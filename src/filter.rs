@@ -0,0 +1,151 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::{anyhow, ensure, Result};
+use serde::{Deserialize, Serialize};
+
+/// Reversible filter applied to a file before compression, so numeric binary data
+/// becomes more predictable. The decompressor undoes it with js_source/delta_filter.js
+/// and js_source/transpose_filter.js before the file is exposed through `wsqz.files`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Filter {
+    /// Replaces every byte with its difference to the byte `distance` bytes before,
+    /// e.g. 4 for the low bytes of 32 bit values
+    Delta(u32),
+    /// Splits records of `stride` bytes into byte planes: all first bytes, then all second bytes...
+    /// Trailing bytes that don't make a full record are left as they are.
+    Transpose(u32),
+}
+
+impl Filter {
+    pub fn apply(&self, data: &[u8]) -> Vec<u8> {
+        match *self {
+            Filter::Delta(distance) => {
+                let distance = distance as usize;
+                data.iter()
+                    .enumerate()
+                    .map(|(i, &byte)| match i.checked_sub(distance) {
+                        Some(prev) => byte.wrapping_sub(data[prev]),
+                        None => byte,
+                    })
+                    .collect()
+            }
+            Filter::Transpose(stride) => {
+                let stride = stride as usize;
+                let rows = data.len() / stride;
+                let mut out: Vec<u8> = (0..stride)
+                    .flat_map(|plane| (0..rows).map(move |row| data[row * stride + plane]))
+                    .collect();
+                out.extend_from_slice(&data[rows * stride..]);
+                out
+            }
+        }
+    }
+
+    /// Undoes `apply`, like the JS decoder
+    #[cfg(test)]
+    pub fn invert(&self, data: &[u8]) -> Vec<u8> {
+        match *self {
+            Filter::Delta(distance) => {
+                let distance = distance as usize;
+                let mut out = data.to_vec();
+                for i in distance..out.len() {
+                    out[i] = out[i].wrapping_add(out[i - distance]);
+                }
+                out
+            }
+            Filter::Transpose(stride) => {
+                let stride = stride as usize;
+                let rows = data.len() / stride;
+                let mut out: Vec<u8> = (0..rows * stride)
+                    .map(|i| data[(i % stride) * rows + i / stride])
+                    .collect();
+                out.extend_from_slice(&data[rows * stride..]);
+                out
+            }
+        }
+    }
+}
+
+impl FromStr for Filter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, arg) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Expected a filter like 'delta:N' or 'transpose:stride'"))?;
+        let arg = arg
+            .parse::<u32>()
+            .map_err(|_| anyhow!("Invalid argument of filter '{}'", s))?;
+
+        match name {
+            "delta" => {
+                ensure!(arg >= 1, "Delta distance must be at least 1");
+                Ok(Filter::Delta(arg))
+            }
+            "transpose" => {
+                ensure!(arg >= 2, "Transpose stride must be at least 2");
+                Ok(Filter::Transpose(arg))
+            }
+            _ => Err(anyhow!("Unknown filter '{}'", name)),
+        }
+    }
+}
+
+impl TryFrom<String> for Filter {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Filter::Delta(distance) => write!(f, "delta:{}", distance),
+            Filter::Transpose(stride) => write!(f, "transpose:{}", stride),
+        }
+    }
+}
+
+impl From<Filter> for String {
+    fn from(filter: Filter) -> Self {
+        filter.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Filter;
+
+    #[test]
+    pub fn round_trip() {
+        let data: Vec<u8> = (0..1001u32).map(|i| (i * i / 7) as u8).collect();
+        for filter in [
+            "delta:1",
+            "delta:4",
+            "transpose:2",
+            "transpose:3",
+            "transpose:2000",
+        ] {
+            let filter: Filter = filter.parse().unwrap();
+            let filtered = filter.apply(&data);
+
+            assert_eq!(filtered.len(), data.len());
+            assert_eq!(filter.invert(&filtered), data, "{}", filter);
+        }
+    }
+
+    #[test]
+    pub fn parse() {
+        assert_eq!("delta:2".parse::<Filter>().unwrap(), Filter::Delta(2));
+        assert_eq!(
+            "transpose:4".parse::<Filter>().unwrap(),
+            Filter::Transpose(4)
+        );
+        assert!("delta:0".parse::<Filter>().is_err());
+        assert!("transpose:1".parse::<Filter>().is_err());
+        assert!("shuffle:4".parse::<Filter>().is_err());
+    }
+}
@@ -0,0 +1,470 @@
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::{bail, Result};
+
+/// Extensions of the files that are minified as shaders
+pub const SHADER_EXTENSIONS: [&str; 3] = ["glsl", "frag", "vert"];
+
+/// Global declarations with these qualifiers are the shader's interface
+/// to the JS and the other shader stage, so their names are kept
+const INTERFACE_QUALIFIERS: &str = "uniform in out attribute varying buffer shared";
+
+const TYPES: &str = "\
+    void bool int uint float double vec2 vec3 vec4 bvec2 bvec3 bvec4 ivec2 ivec3 ivec4 uvec2 \
+    uvec3 uvec4 dvec2 dvec3 dvec4 mat2 mat3 mat4 mat2x2 mat2x3 mat2x4 mat3x2 mat3x3 mat3x4 \
+    mat4x2 mat4x3 mat4x4 sampler2D sampler3D samplerCube sampler2DShadow samplerCubeShadow \
+    sampler2DArray sampler2DArrayShadow isampler2D isampler3D isamplerCube isampler2DArray \
+    usampler2D usampler3D usamplerCube usampler2DArray";
+
+/// Keywords and built-in functions, which renamed identifiers must not collide with
+const RESERVED: &str = "\
+    attribute const uniform varying buffer shared layout centroid flat smooth noperspective \
+    patch sample break continue do for while switch case default if else in out inout true \
+    false invariant precise discard return lowp mediump highp precision struct radians degrees \
+    sin cos tan asin acos atan sinh cosh tanh asinh acosh atanh pow exp log exp2 log2 sqrt \
+    inversesqrt abs sign floor trunc round roundEven ceil fract mod modf min max clamp mix \
+    step smoothstep isnan isinf length distance dot cross normalize faceforward reflect \
+    refract matrixCompMult outerProduct transpose determinant inverse lessThan lessThanEqual \
+    greaterThan greaterThanEqual equal notEqual any all not texture textureSize textureProj \
+    textureLod textureOffset texelFetch textureGrad texture2D textureCube dFdx dFdy fwidth";
+
+fn words(list: &'static str) -> HashSet<&'static str> {
+    list.split_ascii_whitespace().collect()
+}
+
+/// Operators that are longer than one character, longest first
+const OPERATORS: &[&str] = &[
+    "<<=", ">>=", "++", "--", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", "==", "!=", "<=",
+    ">=", "&&", "||", "^^", "<<", ">>",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token<'a> {
+    /// Preprocessor line without comments and with collapsed whitespace
+    Directive(String),
+    Identifier(&'a str),
+    Number(&'a str),
+    /// Operator or punctuation
+    Symbol(&'a str),
+}
+
+/// Shader with comments and whitespace stripped and local identifiers shortened
+#[derive(Debug, Clone)]
+pub struct MinifiedShader {
+    pub source: String,
+    /// Original and new name of every renamed identifier
+    pub renames: Vec<(String, String)>,
+}
+
+fn is_identifier_start(byte: u8) -> bool {
+    byte.is_ascii_alphabetic() || byte == b'_'
+}
+
+fn is_identifier_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_'
+}
+
+/// Replaces comments, including the ones in directives, with a space
+fn strip_comment(src: &[u8], pos: usize) -> Option<usize> {
+    if src[pos..].starts_with(b"//") {
+        Some(
+            src[pos..]
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .map_or(src.len(), |len| pos + len),
+        )
+    } else if src[pos..].starts_with(b"/*") {
+        Some(
+            src[pos + 2..]
+                .windows(2)
+                .position(|end| end == b"*/")
+                .map_or(src.len(), |len| pos + 2 + len + 2),
+        )
+    } else {
+        None
+    }
+}
+
+fn tokenize(src: &str) -> Result<Vec<Token<'_>>> {
+    let bytes = src.as_bytes();
+    let mut tokens = Vec::new();
+    let mut line_start = true;
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        let byte = bytes[pos];
+        if byte.is_ascii_whitespace() {
+            line_start |= byte == b'\n';
+            pos += 1;
+        } else if let Some(end) = strip_comment(bytes, pos) {
+            line_start |= bytes[pos..end].contains(&b'\n');
+            pos = end;
+        } else if byte == b'#' && line_start {
+            // Up to the end of the line that isn't continued with a backslash
+            let mut directive = String::new();
+            while pos < bytes.len() && bytes[pos] != b'\n' {
+                if let Some(end) = strip_comment(bytes, pos) {
+                    directive.push(' ');
+                    pos = end;
+                } else if bytes[pos..].starts_with(b"\\\n") || bytes[pos..].starts_with(b"\\\r\n") {
+                    directive.push(' ');
+                    pos += if bytes[pos + 1] == b'\r' { 3 } else { 2 };
+                } else {
+                    directive.push(bytes[pos] as char);
+                    pos += 1;
+                }
+            }
+            tokens.push(Token::Directive(
+                directive
+                    .split_ascii_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ));
+        } else if is_identifier_start(byte) {
+            let len = bytes[pos..]
+                .iter()
+                .position(|&byte| !is_identifier_byte(byte))
+                .unwrap_or(bytes.len() - pos);
+            tokens.push(Token::Identifier(&src[pos..pos + len]));
+            line_start = false;
+            pos += len;
+        } else if byte.is_ascii_digit()
+            || (byte == b'.' && bytes.get(pos + 1).is_some_and(u8::is_ascii_digit))
+        {
+            let start = pos;
+            while pos < bytes.len() {
+                // Exponents can have a sign, which hex numbers can't
+                let exponent_sign = matches!(bytes[pos], b'+' | b'-')
+                    && matches!(bytes[pos - 1], b'e' | b'E')
+                    && !src[start..pos].starts_with("0x");
+                if !is_identifier_byte(bytes[pos]) && bytes[pos] != b'.' && !exponent_sign {
+                    break;
+                }
+                pos += 1;
+            }
+            tokens.push(Token::Number(&src[start..pos]));
+            line_start = false;
+        } else if byte.is_ascii() {
+            let len = OPERATORS
+                .iter()
+                .find(|op| bytes[pos..].starts_with(op.as_bytes()))
+                .map_or(1, |op| op.len());
+            tokens.push(Token::Symbol(&src[pos..pos + len]));
+            line_start = false;
+            pos += len;
+        } else {
+            bail!("Unexpected non-ASCII character in shader at byte {}", pos);
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Drops redundant zeros of decimal floats, e.g. 0.50 to .5 and 1.0 to 1.
+fn shorten_number(number: &str) -> String {
+    let Some((int, frac)) = number.split_once('.') else {
+        return number.to_owned();
+    };
+    if !int
+        .bytes()
+        .chain(frac.bytes())
+        .all(|byte| byte.is_ascii_digit())
+    {
+        return number.to_owned();
+    }
+
+    let (int, frac) = (int.trim_start_matches('0'), frac.trim_end_matches('0'));
+    match (int.is_empty(), frac.is_empty()) {
+        (true, true) => "0.".to_owned(),
+        _ => format!("{}.{}", int, frac),
+    }
+}
+
+/// Short identifiers in order: a..Z, then aa..Z9...
+fn short_names() -> impl Iterator<Item = String> {
+    const FIRST: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    const REST: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+    (1..).flat_map(|len| {
+        let count = FIRST.len() * REST.len().pow(len - 1);
+        (0..count).map(move |mut idx| {
+            let mut name = vec![FIRST[idx % FIRST.len()]];
+            idx /= FIRST.len();
+            for _ in 1..len {
+                name.push(REST[idx % REST.len()]);
+                idx /= REST.len();
+            }
+            String::from_utf8(name).unwrap()
+        })
+    })
+}
+
+/// Finds the identifiers the shader declares itself and the ones that must keep their name:
+/// the interface (uniforms, ins and outs), structs, macros and `main`
+fn classify<'a>(tokens: &[Token<'a>]) -> (HashSet<&'a str>, HashSet<String>) {
+    let interface_qualifiers = words(INTERFACE_QUALIFIERS);
+    let reserved = words(RESERVED);
+    let mut types = words(TYPES);
+    let mut declared = HashSet::new();
+    let mut kept = HashSet::from(["main".to_owned()]);
+
+    let mut brace_depth = 0i32;
+    let mut paren_depth = 0i32;
+    let mut in_interface = false;
+    // Brace depth of the struct body, the fields are accessed with `.` and keep their name
+    let mut struct_depth = None;
+    // Paren depth of the declaration statement, where commas separate declarations
+    let mut declaration_depth = None;
+
+    for (idx, token) in tokens.iter().enumerate() {
+        let prev = idx.checked_sub(1).map(|prev| &tokens[prev]);
+        let next = tokens.get(idx + 1);
+        match token {
+            Token::Directive(directive) => {
+                // Macros aren't parsed, so every name in them keeps its name
+                kept.extend(
+                    directive
+                        .split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                        .filter(|word| {
+                            word.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                        })
+                        .map(str::to_owned),
+                );
+            }
+            Token::Identifier(name) => {
+                if name.starts_with("gl_") || in_interface || struct_depth.is_some() {
+                    kept.insert(name.to_string());
+                }
+                if brace_depth == 0 && paren_depth == 0 && interface_qualifiers.contains(name) {
+                    in_interface = true;
+                }
+                if *name == "struct" {
+                    if let Some(Token::Identifier(struct_name)) = next {
+                        types.insert(struct_name);
+                    }
+                    struct_depth = Some(brace_depth + 1);
+                }
+
+                let after_type =
+                    matches!(prev, Some(Token::Identifier(prev)) if types.contains(prev));
+                let after_comma =
+                    prev == Some(&Token::Symbol(",")) && declaration_depth == Some(paren_depth);
+                if !types.contains(name) && !reserved.contains(name) && (after_type || after_comma)
+                {
+                    declared.insert(*name);
+                    if after_type && next != Some(&Token::Symbol("(")) {
+                        declaration_depth = Some(paren_depth);
+                    }
+                }
+            }
+            Token::Symbol(symbol) => match *symbol {
+                "(" => paren_depth += 1,
+                ")" => {
+                    paren_depth -= 1;
+                    if declaration_depth.is_some_and(|depth| depth > paren_depth) {
+                        declaration_depth = None;
+                    }
+                }
+                "{" => {
+                    brace_depth += 1;
+                    declaration_depth = None;
+                }
+                "}" => {
+                    brace_depth -= 1;
+                    declaration_depth = None;
+                    if struct_depth.is_some_and(|depth| depth > brace_depth) {
+                        struct_depth = None;
+                    }
+                }
+                ";" => {
+                    declaration_depth = None;
+                    if brace_depth == 0 {
+                        in_interface = false;
+                    }
+                }
+                _ => {}
+            },
+            Token::Number(_) => {}
+        }
+    }
+
+    (declared, kept)
+}
+
+/// Strips comments and whitespace from a shader and renames the identifiers it declares,
+/// except for its interface, to the shortest names by how often they occur
+pub fn minify(src: &str) -> Result<MinifiedShader> {
+    let tokens = tokenize(src)?;
+    let (declared, kept) = classify(&tokens);
+
+    let is_member = |idx: usize| idx > 0 && tokens[idx - 1] == Token::Symbol(".");
+    let mut counts = BTreeMap::<&str, usize>::new();
+    let mut taken = words(RESERVED);
+    taken.extend(words(TYPES));
+    // Names used only in macros would be substituted by the preprocessor
+    taken.extend(kept.iter().map(String::as_str));
+    for (idx, token) in tokens.iter().enumerate() {
+        if let Token::Identifier(name) = token {
+            if is_member(idx) {
+                continue;
+            }
+            if declared.contains(name) && !kept.contains(*name) {
+                *counts.entry(name).or_default() += 1;
+            } else {
+                taken.insert(name);
+            }
+        }
+    }
+
+    let mut by_count: Vec<(&str, usize)> = counts.into_iter().collect();
+    // Most frequent first, ties in name order so the output is deterministic
+    by_count.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    let mut new_names = short_names().filter(|name| !taken.contains(name.as_str()));
+    let renames: BTreeMap<&str, String> = by_count
+        .into_iter()
+        .map(|(name, _)| (name, new_names.next().unwrap()))
+        .collect();
+
+    let mut source = String::new();
+    for (idx, token) in tokens.iter().enumerate() {
+        let text = match token {
+            Token::Directive(directive) => {
+                if !source.is_empty() && !source.ends_with('\n') {
+                    source.push('\n');
+                }
+                source += directive;
+                source.push('\n');
+                continue;
+            }
+            Token::Identifier(name) if !is_member(idx) => {
+                renames.get(name).map_or(name.to_string(), Clone::clone)
+            }
+            Token::Identifier(name) => name.to_string(),
+            Token::Number(number) => shorten_number(number),
+            Token::Symbol(symbol) => symbol.to_string(),
+        };
+
+        if let (Some(last), Some(first)) = (source.bytes().last(), text.bytes().next()) {
+            // Keep tokens from merging into one, e.g. `a - -b` or `return .5`
+            let separate = (is_identifier_byte(last)
+                && (is_identifier_byte(first) || first == b'.'))
+                || (matches!(last, b'+' | b'-') && matches!(first, b'+' | b'-'))
+                || (last == b'/' && matches!(first, b'/' | b'*'));
+            if separate && !matches!(token, Token::Symbol(".")) {
+                source.push(' ');
+            }
+        }
+        source += &text;
+    }
+
+    Ok(MinifiedShader {
+        source,
+        renames: renames
+            .into_iter()
+            .map(|(name, new_name)| (name.to_owned(), new_name))
+            .collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::minify;
+
+    const SHADER: &str = r"#version 300 es
+precision highp float;
+
+// Resolution of the canvas
+uniform vec2 resolution;
+uniform float time;
+in vec2 uv;
+out vec4 color;
+
+struct Hit {
+    float dist;
+    vec3 normal;
+};
+
+#define STEPS 64
+
+/* Distance to a sphere */
+float sphere(vec3 position, float radius) {
+    return length(position) - radius;
+}
+
+Hit march(vec3 origin, vec3 direction) {
+    float dist = 0.0, limit = 100.0;
+    for (int step = 0; step < STEPS; step++) {
+        float d = sphere(origin + direction * dist, 0.50);
+        dist += d;
+        if (d < .001 || dist > limit) break;
+    }
+    return Hit(dist, vec3(0.));
+}
+
+void main() {
+    vec3 direction = normalize(vec3(uv * 2. - 1., -1.0));
+    Hit hit = march(vec3(0, 0, 3), direction);
+    color = vec4(vec3(1.0 - hit.dist * .1 - -time), 1);
+}
+";
+
+    #[test]
+    pub fn keeps_interface() {
+        let minified = minify(SHADER).unwrap();
+        let source = &minified.source;
+
+        assert!(
+            source.starts_with("#version 300 es\nprecision highp float;uniform vec2 resolution;")
+        );
+        for name in ["time", "uv", "color", "main", "dist", "STEPS", "Hit"] {
+            assert!(
+                source.contains(name),
+                "'{}' is missing in: {}",
+                name,
+                source
+            );
+            assert!(!minified.renames.iter().any(|(old, _)| old == name));
+        }
+        assert!(source.contains("\n#define STEPS 64\n"));
+        assert!(source.contains(".dist*.1- -time),1);"));
+        assert!(!source.contains("//") && !source.contains("/*"));
+    }
+
+    #[test]
+    pub fn renames_locals() {
+        let minified = minify(SHADER).unwrap();
+
+        let renamed: Vec<&str> = minified
+            .renames
+            .iter()
+            .map(|(old, _)| old.as_str())
+            .collect();
+        assert_eq!(
+            renamed,
+            [
+                "d",
+                "direction",
+                "hit",
+                "limit",
+                "march",
+                "origin",
+                "position",
+                "radius",
+                "sphere"
+            ]
+        );
+        assert!(minified.source.len() < SHADER.len() * 2 / 3);
+    }
+
+    #[test]
+    pub fn avoids_macro_names() {
+        // `b` only occurs in macros, a local renamed to it would be replaced by `2.`
+        let minified = minify(
+            "#version 300 es\n#define b 2.\n#define SCALE (b * 3.)\nout vec4 color;\n\
+             void main() { float value = SCALE, other = value; color = vec4(value, other, 0, 1); }",
+        )
+        .unwrap();
+        assert_eq!(minified.renames.len(), 2);
+        assert!(minified.renames.iter().all(|(_, new)| new != "b"));
+    }
+}
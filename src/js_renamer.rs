@@ -0,0 +1,337 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use anyhow::{bail, Result};
+use oxc_allocator::Allocator;
+use oxc_ast::{ast::Expression, AstKind};
+use oxc_parser::Parser;
+use oxc_semantic::{ScopeId, SemanticBuilder, SymbolId};
+use oxc_span::SourceType;
+use tracing::debug;
+
+use crate::{compressor::bit_cost, model::Model};
+
+/// Names every local can get, besides the names other locals already have
+const SHORT_NAMES: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ_$";
+
+/// Binding in a function or block scope, which can be renamed without changing the program
+#[derive(Debug)]
+struct Local {
+    name: String,
+    scope: ScopeId,
+    /// The scope of the binding and all its ancestors
+    scope_chain: Vec<ScopeId>,
+    /// Start and end of every occurrence of the name, and if it is a shorthand property
+    /// like `{a}`, which has to keep its key
+    occurrences: Vec<(u32, u32, bool)>,
+    /// Keeps its name, as it occurs in a shorthand assignment target like `({a} = b)`
+    fixed: bool,
+}
+
+impl Local {
+    /// Locals in nested scopes would shadow each other with the same name
+    fn conflicts_with(&self, other: &Local) -> bool {
+        self.scope_chain.contains(&other.scope) || other.scope_chain.contains(&self.scope)
+    }
+}
+
+/// What must stay the same after renaming: the kinds of all nodes in order,
+/// which binding every reference resolves to and the names of the globals
+#[derive(Debug, PartialEq, Eq)]
+struct Shape {
+    node_kinds: Vec<u8>,
+    references: Vec<Option<SymbolId>>,
+    globals: Vec<String>,
+    property_names: Vec<String>,
+}
+
+struct Analysis {
+    locals: Vec<Local>,
+    /// Names of globals and top-level bindings, which locals must not shadow
+    fixed_names: HashSet<String>,
+    shape: Shape,
+}
+
+fn analyze(source: &str) -> Result<Analysis> {
+    let allocator = Allocator::default();
+    let parsed = Parser::new(&allocator, source, SourceType::cjs()).parse();
+    if parsed.panicked || !parsed.errors.is_empty() {
+        bail!(
+            "Failed to parse JS: {}",
+            parsed
+                .errors
+                .first()
+                .map_or("parser panicked".to_owned(), |error| error.to_string())
+        );
+    }
+    let semantic = SemanticBuilder::new().build(&parsed.program).semantic;
+    let scoping = semantic.scoping();
+
+    let mut shorthands = HashSet::new();
+    let mut assignment_shorthands = HashSet::new();
+    let mut shape = Shape {
+        node_kinds: Vec::new(),
+        references: Vec::new(),
+        globals: Vec::new(),
+        property_names: Vec::new(),
+    };
+    for node in semantic.nodes().iter() {
+        let kind = node.kind();
+        shape.node_kinds.push(kind.ty() as u8);
+        match kind {
+            AstKind::IdentifierReference(ident) => {
+                let symbol = scoping.get_reference(ident.reference_id()).symbol_id();
+                shape.references.push(symbol);
+                if symbol.is_none() {
+                    shape.globals.push(ident.name.to_string());
+                }
+            }
+            AstKind::IdentifierName(ident) => shape.property_names.push(ident.name.to_string()),
+            AstKind::ObjectProperty(property) if property.shorthand => {
+                shorthands.insert(property.span.start);
+            }
+            AstKind::BindingProperty(property) if property.shorthand => {
+                shorthands.insert(property.span.start);
+            }
+            AstKind::AssignmentTargetPropertyIdentifier(property) => {
+                assignment_shorthands.insert(property.span.start);
+            }
+            AstKind::WithStatement(_) => bail!("Can't rename locals in code using `with`"),
+            AstKind::CallExpression(call) => {
+                if matches!(&call.callee, Expression::Identifier(callee) if callee.name == "eval") {
+                    bail!("Can't rename locals in code using direct `eval`");
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let root_scope = scoping.root_scope_id();
+    let mut locals = Vec::new();
+    let mut fixed_names: HashSet<String> = scoping
+        .root_unresolved_references()
+        .keys()
+        .map(|name| name.to_string())
+        .collect();
+    for symbol in scoping.symbol_ids() {
+        let name = scoping.symbol_name(symbol).to_owned();
+        let scope = scoping.symbol_scope_id(symbol);
+        if scope == root_scope {
+            fixed_names.insert(name);
+            continue;
+        }
+
+        let occurrence =
+            |span: oxc_span::Span| (span.start, span.end, shorthands.contains(&span.start));
+        let mut occurrences: Vec<_> = std::iter::once(scoping.symbol_span(symbol))
+            .chain(
+                scoping
+                    .symbol_redeclarations(symbol)
+                    .iter()
+                    .map(|redeclaration| redeclaration.span),
+            )
+            .chain(
+                scoping
+                    .get_resolved_references(symbol)
+                    .map(|reference| semantic.reference_span(reference)),
+            )
+            .map(occurrence)
+            .collect();
+        occurrences.sort();
+        occurrences.dedup();
+
+        locals.push(Local {
+            name,
+            scope,
+            scope_chain: scoping.scope_ancestors(scope).collect(),
+            fixed: occurrences
+                .iter()
+                .any(|(start, _, _)| assignment_shorthands.contains(start)),
+            occurrences,
+        });
+    }
+
+    Ok(Analysis {
+        locals,
+        fixed_names,
+        shape,
+    })
+}
+
+/// Writes the source with every local under its name in `names`
+fn render(source: &str, occurrences: &[(u32, u32, bool, usize)], names: &[String]) -> String {
+    let mut out = String::with_capacity(source.len());
+    let mut pos = 0;
+    for &(start, end, shorthand, local) in occurrences {
+        out += &source[pos..start as usize];
+        let original = &source[start as usize..end as usize];
+        if shorthand && names[local] != original {
+            out += original;
+            out.push(':');
+        }
+        out += &names[local];
+        pos = end as usize;
+    }
+    out += &source[pos..];
+    out
+}
+
+/// Result of [`rename_locals`]
+pub struct RenamedSource {
+    pub source: String,
+    pub renamed: usize,
+    pub bits_before: f64,
+    pub bits_after: f64,
+}
+
+/// Gives the local identifiers of `source` the names the model codes cheapest, so that e.g.
+/// the same kind of variable gets the same name in every function.
+/// Greedily tries the names of the other locals and all single character names
+/// for the `max_locals` most frequent locals.
+/// Returns an error if the source can't be renamed safely.
+pub fn rename_locals(
+    source: &str,
+    create_model: &dyn Fn() -> Result<Box<dyn Model>>,
+    max_locals: usize,
+) -> Result<RenamedSource> {
+    let Analysis {
+        locals,
+        fixed_names,
+        shape,
+    } = analyze(source)?;
+
+    let mut occurrences: Vec<(u32, u32, bool, usize)> = locals
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, local)| {
+            local
+                .occurrences
+                .iter()
+                .map(move |&(start, end, shorthand)| (start, end, shorthand, idx))
+        })
+        .collect();
+    occurrences.sort();
+
+    let original_names: Vec<String> = locals.iter().map(|local| local.name.clone()).collect();
+    let mut names = original_names.clone();
+    let mut by_name = HashMap::<String, Vec<usize>>::new();
+    for (idx, name) in names.iter().enumerate() {
+        by_name.entry(name.clone()).or_default().push(idx);
+    }
+
+    let cost = |names: &[String]| -> Result<f64> {
+        Ok(bit_cost(
+            create_model()?,
+            render(source, &occurrences, names).as_bytes(),
+        ))
+    };
+    let bits_before = cost(&names)?;
+    let mut bits = bits_before;
+
+    let mut order: Vec<usize> = (0..locals.len()).collect();
+    order.retain(|&idx| !locals[idx].fixed);
+    order.sort_by_key(|&idx| std::cmp::Reverse(locals[idx].occurrences.len()));
+    order.truncate(max_locals);
+
+    for idx in order {
+        let candidates: BTreeSet<String> = by_name
+            .keys()
+            .cloned()
+            .chain(SHORT_NAMES.chars().map(String::from))
+            .filter(|name| *name != names[idx] && !fixed_names.contains(name))
+            .filter(|name| {
+                by_name.get(name).is_none_or(|others| {
+                    others
+                        .iter()
+                        .all(|&other| !locals[idx].conflicts_with(&locals[other]))
+                })
+            })
+            .collect();
+
+        let current = names[idx].clone();
+        let mut best = None;
+        for candidate in candidates {
+            names[idx] = candidate.clone();
+            let candidate_bits = cost(&names)?;
+            if candidate_bits < bits {
+                bits = candidate_bits;
+                best = Some(candidate);
+            }
+        }
+
+        match best {
+            Some(best) => {
+                debug!("Renamed '{}' to '{}', {:.1} bits", current, best, bits);
+                by_name
+                    .get_mut(&current)
+                    .unwrap()
+                    .retain(|&other| other != idx);
+                if by_name[&current].is_empty() {
+                    by_name.remove(&current);
+                }
+                by_name.entry(best.clone()).or_default().push(idx);
+                names[idx] = best;
+            }
+            None => names[idx] = current,
+        }
+    }
+
+    let renamed_source = render(source, &occurrences, &names);
+    if analyze(&renamed_source)?.shape != shape {
+        bail!("Renamed source doesn't parse to the same program");
+    }
+
+    Ok(RenamedSource {
+        source: renamed_source,
+        renamed: names
+            .iter()
+            .zip(&original_names)
+            .filter(|(name, original)| name != original)
+            .count(),
+        bits_before,
+        bits_after: bits,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{analyze, rename_locals};
+    use crate::compress_config::{create_hash_tables, CompressConfig};
+    use crate::model_finder::create_default_model_config;
+
+    #[test]
+    pub fn keeps_program() {
+        let source = "let g=1;function f(a,b){let c={a,b},{d=2}=c,e;({e}=c);return c.a+b+d+e+g}\
+            function h(e){return[1,2].map(x=>x*e+g)}console.log(f(1,2),h(3));";
+        let config = CompressConfig::default();
+        let layouts = config.hash_table_layouts(source.len()).unwrap();
+        let create_model =
+            || create_default_model_config().create_model(&create_hash_tables(&layouts));
+
+        let renamed = rename_locals(source, &create_model, usize::MAX).unwrap();
+        assert!(renamed.bits_after <= renamed.bits_before);
+        assert_eq!(
+            analyze(&renamed.source).unwrap().shape,
+            analyze(source).unwrap().shape
+        );
+        for kept in [
+            "let g=1;function f(",
+            "function h(",
+            "console.log(f(1,2),h(3));",
+            "e;({e}=",
+        ] {
+            assert!(
+                renamed.source.contains(kept),
+                "'{}' is missing in: {}",
+                kept,
+                renamed.source
+            );
+        }
+    }
+
+    #[test]
+    pub fn rejects_eval() {
+        let source = "function f(a){return eval('a')}";
+        let create_model = || unreachable!();
+        assert!(rename_locals(source, &create_model, usize::MAX).is_err());
+    }
+}
@@ -0,0 +1,105 @@
+let ApmStage = (pow2Size, order, rate) => {
+    let ctx = 0;
+    let maxCount = 255;
+    let currentProbIdx = 0;
+    let mask = (1 << pow2Size) - 1;
+    let prevBytesMask = order >= 8 ? U64Max : (1n << BigInt(order * 8)) - 1n;
+
+    // 32 counters per context, stored as separate items
+    let counters = HashMap(pow2Size + 5, 4, { prob: U24Max >> 1, count: 0 }, (view, value) => {
+        view.setUint32(0, value.prob & U24Max | (value.count << 24));
+    }, (view) => {
+        return {
+            prob: view.getUint32(0) & U24Max,
+            count: view.getUint32(0) >>> 24
+        };
+    });
+    let counterKey = (bitCtx, idx) => ((ctx ^ bitCtx) & mask) * 32 + idx;
+    // Unused counters start out mapping each bucket to its own probability
+    let initProbs = [];
+    for (let i = 0; i < 32; ++i) {
+        initProbs.push((probSquash((i - 16) / 2) * U24Max) | 0);
+    }
+
+    return {
+        refine: (p, bitCtx) => {
+            let pPtr = Math.min(Math.max(p, -8), 7.5) * 2;
+            let pIdxF = Math.floor(pPtr);
+            let pIdxC = Math.ceil(pPtr);
+
+            let deltaF = pPtr - pIdxF;
+            let deltaC = pIdxC - pPtr;
+            let t;
+            let nextIdx;
+            if (deltaF <= deltaC) {
+                currentProbIdx = pIdxF + 16;
+                nextIdx = Math.min(currentProbIdx + 1, 31);
+                t = 1 - deltaF;
+            } else {
+                currentProbIdx = pIdxC + 16;
+                nextIdx = currentProbIdx - 1;
+                t = 1 - deltaC;
+            }
+
+            let counter1 = counters.get(counterKey(bitCtx, currentProbIdx));
+            let counter2 = counters.get(counterKey(bitCtx, nextIdx));
+            if (counter1.count == 0) {
+                counter1.prob = initProbs[currentProbIdx];
+                counters.set(counterKey(bitCtx, currentProbIdx), counter1);
+            }
+            if (counter2.count == 0) {
+                counter2.prob = initProbs[nextIdx];
+                counters.set(counterKey(bitCtx, nextIdx), counter2);
+            }
+
+            return t * (counter1.prob / U24Max) + (1 - t) * (counter2.prob / U24Max);
+        },
+        learn: (bit, bitCtx) => {
+            let value = counters.get(counterKey(bitCtx, currentProbIdx));
+            if (value.count < maxCount) {
+                value.count++;
+            }
+            value.prob += (U24Max * ((bit - (value.prob / U24Max)) / ((value.count + rate) + 1.5))) | 0;
+            counters.set(counterKey(bitCtx, currentProbIdx), value);
+        },
+        updateCtx: (prevBytes) => {
+            let maskedBytes = prevBytes & prevBytesMask;
+            ctx = Number((hash(maskedBytes >> 32n, 3) * 9n + hash(maskedBytes & U32Max, 3)) & U32Max);
+        },
+    };
+};
+
+let AdaptiveProbabilityMap = (model, stages) => {
+    let prevBytes = 0n;
+    let bitCtx = 1;
+
+    return {
+        pred: () => {
+            let p = model.pred();
+
+            let sum = 0;
+            for (let stage of stages) {
+                sum += stage.refine(p, bitCtx);
+            }
+
+            return probStretch(sum / stages.length);
+        },
+        learn: (bit) => {
+            for (let stage of stages) {
+                stage.learn(bit, bitCtx);
+            }
+
+            bitCtx = (bitCtx << 1) | bit;
+            if (bitCtx >= 256) {
+                prevBytes = ((prevBytes << 8n) | BigInt(bitCtx & 0xff)) & U64Max;
+                for (let stage of stages) {
+                    stage.updateCtx(prevBytes);
+                }
+
+                bitCtx = 1;
+            }
+
+            model.learn(bit);
+        },
+    };
+};
@@ -0,0 +1,72 @@
+// Predicts 8 bit unsigned or 16 bit signed little-endian PCM samples
+// from linear predictions of the previous samples of the same channel,
+// bucketed by the last error of each predictor
+let Audio = (bitsPerSample, channels, hashMap, bitHistory) => {
+    let PREDICTORS = 3;
+    let bytesPerSample = bitsPerSample / 8;
+    let contexts = MixedContexts(hashMap, bitHistory, PREDICTORS + 2);
+    let pos = 0;
+    let sample = 0;
+    let samples = Array.from({ length: channels }, () => [0, 0, 0]);
+    let errors = Array.from({ length: channels }, () => [0, 0, 0]);
+    let predictions = [0, 0, 0];
+    let bitCtx = 1;
+    let magicNum = hash(BigInt(0xa000 | bitsPerSample), 2);
+    if (bitHistory) {
+        magicNum = hash((magicNum + 1n) & U32Max, 2);
+    }
+
+    let channel = () => ((pos / bytesPerSample) | 0) % channels;
+    // Sign and magnitude of an error in a few buckets
+    let errorBucket = (error) => (32 - Math.clz32(Math.abs(error))) * 2 + (error < 0);
+
+    let endSample = () => {
+        let value = bytesPerSample == 1 ? sample - 128 : (sample << 16) >> 16;
+        let c = channel();
+        errors[c] = predictions.map(prediction => value - prediction);
+        samples[c] = [value, samples[c][0], samples[c][1]];
+        sample = 0;
+    };
+
+    let updateCtxs = () => {
+        let byteIdx = pos % bytesPerSample;
+        let c = channel();
+        let [s1, s2, s3] = samples[c];
+
+        if (!byteIdx) {
+            let max = (128 << (8 * (bytesPerSample - 1))) - 1;
+            predictions = [s1, 2 * s1 - s2, 3 * s1 - 3 * s2 + s3].map(p => Math.min(Math.max(p, -max - 1), max));
+        }
+
+        // The byte each prediction expects, given the lower bytes of the sample
+        let expectedByte = (prediction) => bytesPerSample == 1
+            ? prediction + 128
+            : !byteIdx ? prediction & 0xff : ((prediction - sample + 128) >> 8) & 0xff;
+
+        contexts.setCtxs([
+            ...predictions.map((prediction, i) => expectedByte(prediction) | (errorBucket(errors[c][i]) << 8)),
+            errorBucket(errors[c][0]) | (errorBucket(errors[c][1]) << 8),
+            expectedByte(s1) | (sample << 8),
+        ], byteIdx, magicNum);
+    };
+
+    return {
+        pred: () => contexts.pred(bitCtx),
+        learn: (bit) => {
+            contexts.learn(bitCtx, bit);
+
+            bitCtx = (bitCtx << 1) | bit;
+            if (bitCtx >= 256) {
+                let byteIdx = pos % bytesPerSample;
+                sample |= (bitCtx & 0xff) << (8 * byteIdx);
+                if (byteIdx == bytesPerSample - 1) {
+                    endSample();
+                }
+                pos++;
+                updateCtxs();
+
+                bitCtx = 1;
+            }
+        },
+    };
+};
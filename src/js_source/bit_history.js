@@ -0,0 +1,92 @@
+// Nonstationary bit history states, as in ZPAQ
+let StateTable = (() => {
+    let N = 50;
+    let numStates = (n0, n1) => {
+        let bound = [20, 48, 15, 8, 6, 5];
+        if (n0 < n1) return numStates(n1, n0);
+        if (n0 < 0 || n1 < 0 || n1 >= bound.length || n0 > bound[n1]) return 0;
+        return 1 + (n1 > 0 && n0 + n1 <= 17);
+    };
+    let discount = (n) => [1, 2, 3, 4, 5, 7, 8].filter(x => n >= x).length;
+    let nextCounts = (n0, n1, bit) => {
+        if (n0 < n1) {
+            let [m1, m0] = nextCounts(n1, n0, 1 - bit);
+            return [m0, m1];
+        }
+
+        if (bit) {
+            n0 = discount(n0);
+            n1++;
+        } else {
+            n0++;
+            n1 = discount(n1);
+        }
+
+        while (!numStates(n0, n1)) {
+            if (n1 < 2) {
+                n0--;
+            } else {
+                n0 = ((n0 * (n1 - 1) + (n1 >> 1)) / n1) | 0;
+                n1--;
+            }
+        }
+
+        return [n0, n1];
+    };
+
+    let states = [];
+    let state = 0;
+    for (let n0 = 0; n0 < N; ++n0) {
+        states.push([]);
+    }
+    for (let total = 0; total < N; ++total) {
+        for (let n1 = 0; n1 <= total; ++n1) {
+            let n = numStates(total - n1, n1);
+            if (n) {
+                states[total - n1][n1] = [state, state + n - 1];
+                state += n;
+            }
+        }
+    }
+
+    // Slots read as states on hash collisions can hold any byte,
+    // unused states restart from the initial state like in the encoder
+    let next = Array.from({ length: 256 }, () => [0, 0]);
+    let counts = Array.from({ length: 256 }, () => [0, 0]);
+    for (let n0 = 0; n0 < N; ++n0) {
+        for (let n1 = 0; n1 < N; ++n1) {
+            for (let lastBit = 0; lastBit < numStates(n0, n1); ++lastBit) {
+                let s = states[n0][n1][lastBit];
+                next[s] = [0, 1].map(bit => {
+                    let [s0, s1] = nextCounts(n0, n1, bit);
+                    return states[s0][s1][bit];
+                });
+                counts[s] = [n0, n1];
+            }
+        }
+    }
+
+    return { next, counts };
+})();
+
+// Maps bit history states to adaptive probabilities
+let StateMap = (maxCount) => {
+    let counters = StateTable.counts.map(([n0, n1]) => ({
+        prob: ((n1 * 2 + 1) * U24Max / ((n0 + n1) * 2 + 2)) | 0,
+        count: 0
+    }));
+
+    return {
+        prob: (state) => counters[state].prob,
+        learn: (state, bit) => {
+            let counter = counters[state];
+            if (counter.count < maxCount) {
+                counter.count++;
+            }
+            counter.prob += (U24Max * ((bit - (counter.prob / U24Max)) / (counter.count + 1.5))) | 0;
+            counter.prob &= U24Max;
+            return StateTable.next[state][bit];
+        },
+        count: (state) => StateTable.counts[state][0] + StateTable.counts[state][1],
+    };
+};
@@ -0,0 +1,62 @@
+// Uses the character above in the previous line and the column as context,
+// or the indentation depth and first character of the line if isIndentation is set
+let Column = (isIndentation, hashMap, bitHistory) => {
+    // Longest line prefix kept, the column context saturates there
+    let MAX_COLUMN = 256;
+    let ctx = 0;
+    let bitCtx = 1;
+    let line = [];
+    let prevLine = [];
+    // Number of leading spaces and tabs of the current line
+    let indentation = 0;
+    // First character of the current line after the indentation, 0 if not seen yet
+    let firstChar = 0;
+    let magicNum = hash(BigInt(0xc010 | isIndentation), 2);
+    let slots = ContextSlots(hashMap, bitHistory);
+    if (bitHistory) {
+        magicNum = hash((magicNum + 1n) & U32Max, 2);
+    }
+
+    let updateLine = (byte) => {
+        if (byte == 10) {
+            [prevLine, line] = [line, prevLine];
+            line.length = 0;
+            indentation = 0;
+            firstChar = 0;
+            return;
+        }
+
+        if (!firstChar) {
+            if (byte == 32 || byte == 9) {
+                indentation++;
+            } else {
+                firstChar = byte;
+            }
+        }
+        if (line.length < MAX_COLUMN) {
+            line.push(byte);
+        }
+    };
+
+    return {
+        pred: () => slots.pred(ctx ^ bitCtx),
+        learn: (bit) => {
+            slots.learn(ctx ^ bitCtx, bit);
+
+            bitCtx = (bitCtx << 1) | bit;
+            if (bitCtx >= 256) {
+                let currentByte = bitCtx & 0xff;
+                updateLine(currentByte);
+
+                let column = line.length;
+                let a = isIndentation
+                    ? Math.min(indentation, 255) | (firstChar << 8)
+                    : (prevLine[column] || 0) | (Math.min(column, 255) << 8);
+                let b = isIndentation ? currentByte : 0;
+                ctx = Number(((hash(BigInt(a), 3) * 9n + hash(BigInt(b), 3) + 1n) * magicNum) & U32Max);
+
+                bitCtx = 1;
+            }
+        },
+    };
+};
@@ -0,0 +1,4 @@
+// Undoes the delta filter of data[start, end) in place
+let undelta = (data, start, end, distance) => {
+    for (let i = start + distance; i < end; ++i) data[i] += data[i - distance];
+};
@@ -0,0 +1,70 @@
+// Predicts little-endian float32 arrays from the corresponding bytes of the
+// previous elements, where an element consists of stride floats
+let Float32 = (stride, hashMap, bitHistory) => {
+    let strideSize = stride * 4;
+    let contexts = MixedContexts(hashMap, bitHistory, 6);
+    let historySize = 1;
+    while (historySize < 8 * stride + 4) {
+        historySize *= 2;
+    }
+    let history = new Uint8Array(historySize);
+    let pos = 0;
+    let bitCtx = 1;
+    let magicNum = hash(0xf320n, 2);
+    if (bitHistory) {
+        magicNum = hash((magicNum + 1n) & U32Max, 2);
+    }
+
+    let floatView = new DataView(new ArrayBuffer(4));
+    let floatAt = (start) => {
+        for (let i = 0; i < 4; ++i) {
+            floatView.setUint8(i, history[(start + i) & (historySize - 1)]);
+        }
+        return floatView.getUint32(0, true);
+    };
+    let toFloat = (bits) => (floatView.setUint32(0, bits, true), floatView.getFloat32(0, true));
+
+    let updateCtxs = () => {
+        let byteIdx = pos % 4;
+        let start = pos - byteIdx;
+        let prev1 = floatAt(start - strideSize);
+        let prev2 = floatAt(start - 2 * strideSize);
+        let adjacent = floatAt(start - 4);
+        // Lower bytes of the current float, which are already known
+        let current = floatAt(start) & (2 ** (8 * byteIdx) - 1);
+
+        // Linear extrapolation of the previous elements
+        let extrapolated = Math.fround(2 * toFloat(prev1) - toFloat(prev2));
+        extrapolated = isNaN(extrapolated) ? 0 : (floatView.setFloat32(0, extrapolated, true), floatView.getUint32(0, true));
+
+        // The byte being coded and the more significant ones
+        let high = (value) => value >>> (8 * byteIdx);
+        let byte = (value) => high(value) & 0xff;
+        let prevByte = byteIdx ? byte(current << 8) : 0;
+        contexts.setCtxs([
+            high(prev1),
+            byte(prev1) | (byte(prev2) << 8),
+            high(extrapolated),
+            byte(prev1) | (byte(adjacent) << 8),
+            // Sign, exponent and highest mantissa bits of the previous element
+            (prev1 >>> 16) | (prevByte << 16),
+            byte(adjacent) | (prevByte << 8) | (byte(prev1) << 16),
+        ], byteIdx, magicNum);
+    };
+
+    return {
+        pred: () => contexts.pred(bitCtx),
+        learn: (bit) => {
+            contexts.learn(bitCtx, bit);
+
+            bitCtx = (bitCtx << 1) | bit;
+            if (bitCtx >= 256) {
+                history[pos & (historySize - 1)] = bitCtx & 0xff;
+                pos++;
+                updateCtxs();
+
+                bitCtx = 1;
+            }
+        },
+    };
+};
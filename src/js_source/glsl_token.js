@@ -0,0 +1,52 @@
+// Word model with a GLSL tokenizer. Uses the hashes of the previous two tokens
+// (keywords, identifiers and swizzles, numbers, operators and punctuation)
+// and the current token as context.
+let GlslToken = (hashMap, bitHistory) => {
+    let WHITESPACE = 0, WORD = 1, OPERATOR = 2, PUNCTUATION = 3;
+    let FNV_BASIS = 2166136261;
+
+    let ctx = 0;
+    let bitCtx = 1;
+    // Hash of the current token so far
+    let token = FNV_BASIS;
+    let tokenClass = WHITESPACE;
+    let prevTokens = [FNV_BASIS, FNV_BASIS];
+    let magicNum = hash(0x6151n, 2);
+    let slots = ContextSlots(hashMap, bitHistory);
+    if (bitHistory) {
+        magicNum = hash((magicNum + 1n) & U32Max, 2);
+    }
+
+    let charClass = (char) => /[a-zA-Z0-9_#]/.test(char) ? WORD
+        // Brackets and separators are always tokens of their own
+        : '()[]{};,.'.includes(char) ? PUNCTUATION
+        : '+-*/%=<>!&|^~?:'.includes(char) ? OPERATOR
+        : WHITESPACE;
+
+    return {
+        pred: () => slots.pred(ctx ^ bitCtx),
+        learn: (bit) => {
+            slots.learn(ctx ^ bitCtx, bit);
+
+            bitCtx = (bitCtx << 1) | bit;
+            if (bitCtx >= 256) {
+                let byte = bitCtx & 0xff;
+                let charClassOfByte = charClass(String.fromCharCode(byte));
+                if (tokenClass != WHITESPACE && (charClassOfByte != tokenClass || charClassOfByte == PUNCTUATION)) {
+                    prevTokens = [token, prevTokens[0]];
+                    token = FNV_BASIS;
+                }
+
+                tokenClass = charClassOfByte;
+                if (tokenClass != WHITESPACE) {
+                    token = Math.imul(token ^ byte, 16777619) >>> 0;
+                }
+
+                let prevTokensHash = (Math.imul(prevTokens[0], 0x2545F491) ^ prevTokens[1]) >>> 0;
+                ctx = norderHash((BigInt(prevTokensHash) << 32n) | BigInt(token), magicNum);
+
+                bitCtx = 1;
+            }
+        },
+    };
+};
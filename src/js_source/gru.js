@@ -0,0 +1,144 @@
+// Small byte-level recurrent network (GRU) that learns online. The previous
+// byte updates the hidden state, which predicts the bits of the next byte.
+// Activations avoid Math.exp to match the encoder exactly.
+let Gru = (cells) => {
+    let UPDATE = 0, RESET = 1, CANDIDATE = 2;
+    let OUTPUT_LEARNING_RATE = 0.5, LEARNING_RATE = 0.2;
+
+    let rng = 0x2545F491;
+    // Uniform in [-scale / 2, scale / 2), from a xorshift generator
+    let randomWeight = (scale) => {
+        rng ^= rng << 13;
+        rng ^= rng >>> 17;
+        rng ^= rng << 5;
+        rng >>>= 0;
+        return (rng / 4294967296 - 0.5) * scale;
+    };
+    let softsign = (x) => x / (1 + Math.abs(x));
+    // Logistic function of x in [-16, 16] with basic arithmetic only
+    let squash = (x) => {
+        let LN_2 = 0.6931471805599453;
+        let k = Math.floor(-x / LN_2 + 0.5);
+        let r = -x - k * LN_2;
+        let exp = 1;
+        for (let i = 10; i >= 1; --i) {
+            exp = 1 + exp * r / i;
+        }
+        let pow2 = 1 << Math.abs(k);
+        exp = k < 0 ? exp / pow2 : exp * pow2;
+        return 1 / (1 + exp);
+    };
+    let softsignGrad = (x) => {
+        let d = 1 + Math.abs(x);
+        return 1 / (d * d);
+    };
+
+    // [byte][gate][cell]
+    let inputWeights = new Float64Array(256 * 3 * cells).map(() => randomWeight(1));
+    // [gate][cell][cell]
+    let scale = 2 / Math.sqrt(cells);
+    let recurrentWeights = new Float64Array(3 * cells * cells).map(() => randomWeight(scale));
+    // [bitCtx][cell]
+    let outputWeights = new Float64Array(256 * cells);
+    let outputBiases = new Float64Array(256);
+
+    let hidden = new Float64Array(cells);
+    // Values of the last step needed for training it
+    let prevHidden = new Float64Array(cells);
+    let resetHidden = new Float64Array(cells);
+    let updateGate = new Float64Array(cells);
+    let candidate = new Float64Array(cells);
+    // [gate][cell]
+    let activationGrads = new Float64Array(3 * cells);
+    let hiddenGrad = new Float64Array(cells);
+    // Gradients of the gate and candidate inputs, [gate][cell]
+    let gateGrads = new Float64Array(3 * cells);
+
+    let prevByte = 0;
+    let logit = 0;
+    let bitCtx = 1;
+
+    let gateInput = (gate, cell, values) => {
+        let sum = inputWeights[(prevByte * 3 + gate) * cells + cell];
+        for (let j = 0; j < cells; ++j) {
+            sum += recurrentWeights[(gate * cells + cell) * cells + j] * values[j];
+        }
+        return sum;
+    };
+
+    let step = () => {
+        prevHidden.set(hidden);
+        for (let i = 0; i < cells; ++i) {
+            let update = gateInput(UPDATE, i, prevHidden);
+            let reset = gateInput(RESET, i, prevHidden);
+            updateGate[i] = 0.5 + 0.5 * softsign(update);
+            activationGrads[UPDATE * cells + i] = 0.5 * softsignGrad(update);
+            resetHidden[i] = (0.5 + 0.5 * softsign(reset)) * prevHidden[i];
+            activationGrads[RESET * cells + i] = 0.5 * softsignGrad(reset);
+        }
+
+        for (let i = 0; i < cells; ++i) {
+            let c = gateInput(CANDIDATE, i, resetHidden);
+            candidate[i] = softsign(c);
+            activationGrads[CANDIDATE * cells + i] = softsignGrad(c);
+            hidden[i] = prevHidden[i] + updateGate[i] * (candidate[i] - prevHidden[i]);
+        }
+    };
+
+    // Trains the last step without propagating further back in time
+    let trainStep = () => {
+        for (let i = 0; i < cells; ++i) {
+            gateGrads[UPDATE * cells + i] = hiddenGrad[i] * (candidate[i] - prevHidden[i]) * activationGrads[UPDATE * cells + i];
+            gateGrads[CANDIDATE * cells + i] = hiddenGrad[i] * updateGate[i] * activationGrads[CANDIDATE * cells + i];
+        }
+
+        for (let j = 0; j < cells; ++j) {
+            let sum = 0;
+            for (let i = 0; i < cells; ++i) {
+                sum += gateGrads[CANDIDATE * cells + i] * recurrentWeights[(CANDIDATE * cells + i) * cells + j];
+            }
+            gateGrads[RESET * cells + j] = sum * prevHidden[j] * activationGrads[RESET * cells + j];
+        }
+
+        for (let gate = 0; gate < 3; ++gate) {
+            let values = gate == CANDIDATE ? resetHidden : prevHidden;
+            for (let i = 0; i < cells; ++i) {
+                let step = LEARNING_RATE * gateGrads[gate * cells + i];
+                inputWeights[(prevByte * 3 + gate) * cells + i] += step;
+                for (let j = 0; j < cells; ++j) {
+                    recurrentWeights[(gate * cells + i) * cells + j] += step * values[j];
+                }
+            }
+        }
+
+        hiddenGrad.fill(0);
+    };
+
+    return {
+        pred: () => {
+            let sum = outputBiases[bitCtx];
+            for (let i = 0; i < cells; ++i) {
+                sum += outputWeights[bitCtx * cells + i] * hidden[i];
+            }
+            logit = Math.min(Math.max(sum, -16), 16);
+            return logit;
+        },
+        learn: (bit) => {
+            let err = bit - squash(logit);
+            outputBiases[bitCtx] += OUTPUT_LEARNING_RATE * err;
+            for (let i = 0; i < cells; ++i) {
+                hiddenGrad[i] += err * outputWeights[bitCtx * cells + i];
+                outputWeights[bitCtx * cells + i] += OUTPUT_LEARNING_RATE * err * hidden[i];
+            }
+
+            bitCtx = (bitCtx << 1) | bit;
+            if (bitCtx >= 256) {
+                trainStep();
+                prevByte = bitCtx & 0xff;
+                step();
+
+                bitCtx = 1;
+            }
+        },
+    };
+};
@@ -1,19 +1,59 @@
-let HashMap = (pow2Size, itemSize, defaultValue, itemEncoder, itemDecoder) => {
+// When priority is set, keys are verified with a check byte and stored in buckets
+// of 4 items, replacing the item with the lowest priority when a bucket is full.
+// Items with zero priority are considered unused.
+let HashMap = (pow2Size, itemSize, defaultValue, itemEncoder, itemDecoder, priority) => {
     let data = new Uint8Array(2 ** pow2Size * itemSize);
-    let mask = (1 << pow2Size) - 1;
+    let mask = 2 ** pow2Size - 1;
+    let tags = priority && new Uint8Array(2 ** pow2Size);
 
-    let defaultValueBuffer = new Uint8Array(itemSize);
-    itemEncoder(new DataView(defaultValueBuffer.buffer), defaultValue);
-    for (let i = 0; i < 2 ** pow2Size; i++) {
-        data.set(defaultValueBuffer, i * itemSize);
+    itemEncoder(new DataView(data.buffer), defaultValue);
+    // Fill the rest by doubling, large tables take too long to fill item by item
+    for (let filled = itemSize; filled < data.length; filled *= 2) {
+        data.copyWithin(filled, 0, filled);
     }
 
+    let itemAt = (idx) => itemDecoder(new DataView(data.buffer, idx * itemSize));
+    // Check byte from the low 2 bits and the bits above the index folded into 6 bits
+    let tag = (key) => {
+        let high = key >>> pow2Size;
+        return (((high ^ (high >>> 6) ^ (high >>> 12) ^ (high >>> 18) ^ (high >>> 24)) & 0x3f) << 2) | (key & 3);
+    };
+    let find = (key) => {
+        let bucket = key & mask & ~3;
+        for (let idx = bucket; idx < bucket + 4; ++idx) {
+            if (tags[idx] == tag(key) && priority(itemAt(idx)) > 0) {
+                return idx;
+            }
+        }
+        return -1;
+    };
+
     return {
         get: (key) => {
-            return itemDecoder(new DataView(data.buffer, (key & mask) * itemSize));
+            if (!tags) {
+                return itemAt(key & mask);
+            }
+
+            let idx = find(key);
+            return idx < 0 ? { ...defaultValue } : itemAt(idx);
         },
         set: (key, value) => {
-            itemEncoder(new DataView(data.buffer, (key & mask) * itemSize), value);
+            let idx = key & mask;
+            if (tags) {
+                idx = find(key);
+                if (idx < 0) {
+                    let bucket = key & mask & ~3;
+                    idx = bucket;
+                    for (let i = bucket + 1; i < bucket + 4; ++i) {
+                        if (priority(itemAt(i)) < priority(itemAt(idx))) {
+                            idx = i;
+                        }
+                    }
+                    tags[idx] = tag(key);
+                }
+            }
+
+            itemEncoder(new DataView(data.buffer, idx * itemSize), value);
         }
     };
 }
@@ -22,4 +62,4 @@ function hash(value, shift) {
     const K_MUL = 0x9E35A7BDn;
     value ^= value >> BigInt(shift);
     return ((K_MUL * value) & 0xffffffffn) >> BigInt(shift);
-}
\ No newline at end of file
+}
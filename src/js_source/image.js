@@ -0,0 +1,51 @@
+// Predicts raw pixel data from the west, north, north-west and north-east
+// neighbours of the same channel and simple gradients of them.
+let Image = (width, channels, bytesPerChannel, hashMap, bitHistory) => {
+    let pixelSize = channels * bytesPerChannel;
+    let rowSize = width * pixelSize;
+    let contexts = MixedContexts(hashMap, bitHistory, 7);
+    let historySize = 1;
+    while (historySize < 2 * rowSize + 4 * pixelSize) {
+        historySize *= 2;
+    }
+    let history = new Uint8Array(historySize);
+    let pos = 0;
+    let bitCtx = 1;
+    let magicNum = hash(0x1a00n, 2);
+    if (bitHistory) {
+        magicNum = hash((magicNum + 1n) & U32Max, 2);
+    }
+
+    let updateCtxs = () => {
+        let at = (back) => history[(pos - back) & (historySize - 1)];
+        let w = at(pixelSize), n = at(rowSize), nw = at(rowSize + pixelSize), ne = at(rowSize - pixelSize);
+        let ww = at(2 * pixelSize), nn = at(2 * rowSize);
+
+        // Byte within the pixel, so channels don't share statistics
+        contexts.setCtxs([
+            w | (n << 8),
+            n | (nw << 8) | (ne << 16),
+            w | (ww << 8),
+            n | (nn << 8),
+            Math.min(Math.max(w + n - nw, 0), 255),
+            Math.min(Math.max(w + ne - n, 0), 255),
+            (w + n + 1) >> 1,
+        ], pos % pixelSize, magicNum);
+    };
+
+    return {
+        pred: () => contexts.pred(bitCtx),
+        learn: (bit) => {
+            contexts.learn(bitCtx, bit);
+
+            bitCtx = (bitCtx << 1) | bit;
+            if (bitCtx >= 256) {
+                history[pos & (historySize - 1)] = bitCtx & 0xff;
+                pos++;
+                updateCtxs();
+
+                bitCtx = 1;
+            }
+        },
+    };
+};
@@ -0,0 +1,22 @@
+// Restricts a model to a byte range of the input, e.g. a single file.
+// The model only sees the bytes of the range and predicts nothing outside of it.
+let InRange = (start, end, model) => {
+    let pos = 0;
+    let bitCtx = 1;
+    let isActive = () => pos >= start && pos < end;
+
+    return {
+        pred: () => isActive() ? model.pred() : 0,
+        learn: (bit) => {
+            if (isActive()) {
+                model.learn(bit);
+            }
+
+            bitCtx = (bitCtx << 1) | bit;
+            if (bitCtx >= 256) {
+                pos++;
+                bitCtx = 1;
+            }
+        },
+    };
+};
@@ -0,0 +1,35 @@
+// Predicts from the bytes that followed the last two occurrences
+// of the order-1 or order-2 context, instead of the context itself
+let Indirect = (order, hashMap, bitHistory) => {
+    let ctx = 0;
+    let bitCtx = 1;
+    let prevBytes = 0;
+    let orderMask = 2 ** (order * 8) - 1;
+    let histories = new Uint16Array(orderMask + 1);
+    let magicNum = hash(BigInt(0x1d00 | order), 2);
+    let slots = ContextSlots(hashMap, bitHistory);
+    if (bitHistory) {
+        magicNum = hash((magicNum + 1n) & U32Max, 2);
+    }
+
+    return {
+        pred: () => slots.pred(ctx ^ bitCtx),
+        learn: (bit) => {
+            slots.learn(ctx ^ bitCtx, bit);
+
+            bitCtx = (bitCtx << 1) | bit;
+            if (bitCtx >= 256) {
+                let currentByte = bitCtx & 0xff;
+
+                // Append the byte to the history of the context it followed
+                let idx = prevBytes & orderMask;
+                histories[idx] = (histories[idx] << 8) | currentByte;
+
+                prevBytes = ((prevBytes << 8) | currentByte) & orderMask;
+                ctx = Number(((hash(BigInt(histories[prevBytes]), 3) + 1n) * magicNum) & U32Max);
+
+                bitCtx = 1;
+            }
+        },
+    };
+};
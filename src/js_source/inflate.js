@@ -0,0 +1,2 @@
+// Inflates raw deflate data with the browser's decompressor
+let inflate = async data => new Uint8Array(await new Response(new Response(data).body.pipeThrough(new DecompressionStream("deflate-raw"))).arrayBuffer());
@@ -0,0 +1,148 @@
+// Mixes the state of a lightweight JavaScript lexer (literal or comment,
+// bracket depth and previous token class) with the last order bytes.
+// Mistakes of the lexer only cost compression, as long as the encoder does the same.
+let JsLexer = (order, hashMap, bitHistory) => {
+    // States
+    let CODE = 0, SINGLE_QUOTE = 1, DOUBLE_QUOTE = 2, TEMPLATE = 3, REGEXP = 4, REGEXP_CLASS = 5, LINE_COMMENT = 6, BLOCK_COMMENT = 7;
+    // Token classes
+    let NONE = 0, WORD = 1, NUMBER = 2, OPERATOR = 3, CLOSE = 4, LITERAL = 5;
+
+    let ctx = 0;
+    let bitCtx = 1;
+    let prevBytes = 0n;
+    let mask = (1n << BigInt(8 * order)) - 1n;
+    let state = CODE;
+    let depth = 0;
+    let tokenClass = NONE;
+    // Whitespace followed the last token, so a word or number starts a new one
+    let tokenEnded = 0;
+    // Nesting depths of the ${ substitutions of enclosing template literals
+    let templateDepths = [];
+    let escaped = 0;
+    let regexpStart = 0;
+    let division = 0;
+    let prevByte = 0;
+    let magicNum = hash(BigInt(0x1e40 | order), 2);
+    let slots = ContextSlots(hashMap, bitHistory);
+    if (bitHistory) {
+        magicNum = hash((magicNum + 1n) & U32Max, 2);
+    }
+
+    let update = (byte) => {
+        let prev = prevByte;
+        prevByte = byte;
+
+        // Escaped characters in literals are skipped
+        if (escaped) {
+            escaped = 0;
+            return;
+        }
+        if (byte == 92 && state != CODE && state != LINE_COMMENT && state != BLOCK_COMMENT) {
+            escaped = 1;
+            return;
+        }
+
+        let char = String.fromCharCode(byte);
+        if (state == CODE) {
+            let wasDivision = division;
+            division = 0;
+            if (wasDivision && (char == '/' || char == '*')) {
+                state = char == '/' ? LINE_COMMENT : BLOCK_COMMENT;
+            } else if (char == "'") {
+                state = SINGLE_QUOTE;
+            } else if (char == '"') {
+                state = DOUBLE_QUOTE;
+            } else if (char == '`') {
+                state = TEMPLATE;
+            } else if (char == '/') {
+                // A regexp can't follow an operand
+                if (tokenClass == NONE || tokenClass == OPERATOR) {
+                    state = REGEXP;
+                    regexpStart = 1;
+                } else {
+                    tokenClass = OPERATOR;
+                    division = 1;
+                }
+            } else if ('([{'.includes(char)) {
+                depth++;
+                tokenClass = OPERATOR;
+            } else if (')]}'.includes(char)) {
+                depth = Math.max(depth - 1, 0);
+                tokenClass = CLOSE;
+                if (char == '}' && templateDepths[templateDepths.length - 1] === depth) {
+                    templateDepths.pop();
+                    state = TEMPLATE;
+                }
+            } else if (byte >= 48 && byte <= 57) {
+                if (tokenClass != WORD || tokenEnded) {
+                    tokenClass = NUMBER;
+                }
+                tokenEnded = 0;
+            } else if (/[a-zA-Z_$]/.test(char) || byte >= 128) {
+                if (tokenClass != NUMBER || tokenEnded) {
+                    tokenClass = WORD;
+                }
+                tokenEnded = 0;
+            } else if (' \t\r\n'.includes(char)) {
+                tokenEnded = 1;
+            } else {
+                tokenClass = OPERATOR;
+            }
+        } else if (state == SINGLE_QUOTE || state == DOUBLE_QUOTE) {
+            if (char == (state == SINGLE_QUOTE ? "'" : '"') || byte == 10) {
+                state = CODE;
+                tokenClass = LITERAL;
+            }
+        } else if (state == TEMPLATE) {
+            if (char == '`') {
+                state = CODE;
+                tokenClass = LITERAL;
+            } else if (char == '{' && prev == 36) {
+                templateDepths.push(depth);
+                depth++;
+                state = CODE;
+                tokenClass = OPERATOR;
+            }
+        } else if (state == REGEXP) {
+            let wasStart = regexpStart;
+            regexpStart = 0;
+            if (wasStart && (char == '/' || char == '*')) {
+                state = char == '/' ? LINE_COMMENT : BLOCK_COMMENT;
+            } else if (char == '[') {
+                state = REGEXP_CLASS;
+            } else if (char == '/' || byte == 10) {
+                state = CODE;
+                tokenClass = LITERAL;
+            }
+        } else if (state == REGEXP_CLASS) {
+            if (char == ']') {
+                state = REGEXP;
+            }
+        } else if (state == LINE_COMMENT) {
+            if (byte == 10) {
+                state = CODE;
+            }
+        } else if (char == '/' && prev == 42) {
+            state = CODE;
+        }
+    };
+
+    return {
+        pred: () => slots.pred(ctx ^ bitCtx),
+        learn: (bit) => {
+            slots.learn(ctx ^ bitCtx, bit);
+
+            bitCtx = (bitCtx << 1) | bit;
+            if (bitCtx >= 256) {
+                let currentByte = bitCtx & 0xff;
+                update(currentByte);
+                prevBytes = ((prevBytes << 8n) | BigInt(currentByte)) & U32Max;
+
+                let lexerCtx = state | (Math.min(depth, 15) << 3) | (tokenClass << 7);
+                ctx = norderHash((BigInt(lexerCtx) << 32n) | (prevBytes & mask), magicNum);
+
+                bitCtx = 1;
+            }
+        },
+    };
+};
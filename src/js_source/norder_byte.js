@@ -1,21 +1,96 @@
 let ASCII_CASE_MASK = 32;
 
-let NOrderByteHashMap = HashMap(26, 4, { prob: U24Max >> 1, count: 0 }, (view, value) => {
+let NOrderByteHashMap = (pow2Size, checked) => HashMap(pow2Size, 4, { prob: U24Max >> 1, count: 0 }, (view, value) => {
     view.setUint32(0, value.prob & U24Max | (value.count << 24));
 }, (view) => {
     return {
         prob: view.getUint32(0) & U24Max,
         count: view.getUint32(0) >>> 24
     };
-});
+}, checked && ((value) => value.count));
 
-let NOrderByte = (byteMask, isWord) => {
-    let ctx = 0;
+// Predicts bits from the slots of a hash map, keyed by a context hash
+// computed by the owning model
+let ContextSlots = (hashMap, bitHistory) => {
     let maxCount = 15;
+    // Slots store bit history states instead of probabilities when set
+    let stateMap = bitHistory && StateMap(255);
+    // Unused slots (zero count) are in the initial state
+    let slotState = (value) => value.count ? value.prob >>> 16 : 0;
+
+    return {
+        pred: (key) => {
+            let value = hashMap.get(key);
+            return probStretch((stateMap ? stateMap.prob(slotState(value)) : value.prob) / U24Max);
+        },
+        learn: (key, bit) => {
+            let value = hashMap.get(key);
+            if (stateMap) {
+                let state = stateMap.learn(slotState(value), bit);
+                value = { prob: state << 16 | 0x8000, count: Math.max(stateMap.count(state), 1) };
+            } else {
+                if (value.count < maxCount) {
+                    value.count++;
+                }
+                let countSqrt = value.count + 0.2;
+                value.prob += (U24Max * ((bit - (value.prob / U24Max)) / countSqrt)) | 0;
+            }
+            hashMap.set(key, value);
+        },
+    };
+};
+
+// Slots of several contexts, whose predictions are mixed by a small
+// mixer so a model can contribute all of them as a single prediction
+let MixedContexts = (hashMap, bitHistory, count) => {
+    let LEARNING_RATE = 0.002;
+    let slots = ContextSlots(hashMap, bitHistory);
+    let ctxs = new Array(count).fill(0);
+    let weights = new Array(count).fill(0.3);
+    let lastP = new Array(count);
+    let lastTotalP = 0;
+
+    return {
+        pred: (bitCtx) => {
+            let sum = 0;
+            for (let i = 0; i < count; ++i) {
+                let p = slots.pred(ctxs[i] ^ bitCtx);
+                lastP[i] = p;
+                sum += p * weights[i];
+            }
+
+            lastTotalP = probSquash(sum);
+            return sum;
+        },
+        learn: (bitCtx, bit) => {
+            let predErr = bit - lastTotalP;
+            for (let i = 0; i < count; ++i) {
+                slots.learn(ctxs[i] ^ bitCtx, bit);
+                weights[i] += LEARNING_RATE * predErr * lastP[i];
+            }
+        },
+        // Hashes the context values, values[i] being the context of slot i.
+        // selector separates the statistics of e.g. the bytes within a sample.
+        setCtxs: (values, selector, magicNum) => values.forEach((value, i) => {
+            ctxs[i] = Number(((hash(BigInt(value >>> 0), 3) + hash(BigInt(i | (selector << 8)), 3) * 0x2545F491n + 1n) * magicNum) & U32Max);
+        }),
+    };
+};
+
+// Context hash of NOrderByte models, magicNum separates the models
+let norderHash = (maskedBytes, magicNum) =>
+    Number((((hash(maskedBytes >> 32n, 3) * 9n + hash(maskedBytes & U32Max, 3)) + 1n) * magicNum) & U32Max);
+
+let NOrderByte = (byteMask, isWord, hashMap, bitHistory) => {
+    let ctx = 0;
     let bitMask = 0n;
     let bitCtx = 1;
     let prevBytes = isWord ? 2166136261n : 0n;
     let magicNum = hash(isWord ? 1337n : BigInt(byteMask), 2);
+    let slots = ContextSlots(hashMap, bitHistory);
+    if (bitHistory) {
+        magicNum = hash((magicNum + 1n) & U32Max, 2);
+    }
 
     for (let i = 0; i < 8; i++) {
         bitMask |= BigInt((byteMask >>> i) & 1) * (BigInt(0xff) << BigInt(i * 8));
@@ -23,17 +98,9 @@ let NOrderByte = (byteMask, isWord) => {
     bitMask = isWord ? U64Max : bitMask;
 
     return {
-        pred: () => {
-            return probStretch(NOrderByteHashMap.get(ctx ^ bitCtx).prob / U24Max);
-        },
+        pred: () => slots.pred(ctx ^ bitCtx),
         learn: (bit) => {
-            let value = NOrderByteHashMap.get(ctx ^ bitCtx);
-            if (value.count < maxCount) {
-                value.count++;
-            }
-            let countSqrt = value.count + 0.2;
-            value.prob += (U24Max * ((bit - (value.prob / U24Max)) / countSqrt)) | 0;
-            NOrderByteHashMap.set(ctx ^ bitCtx, value);
+            slots.learn(ctx ^ bitCtx, bit);
 
             bitCtx = (bitCtx << 1) | bit;
             if (bitCtx >= 256) {
@@ -53,8 +120,7 @@ let NOrderByte = (byteMask, isWord) => {
                     prevBytes = ((prevBytes << 8n) | BigInt(currentByte)) & U64Max;
                 }
 
-                let maskedBytes = prevBytes & bitMask;
-                ctx = Number((((hash(maskedBytes >> 32n, 3) * 9n + hash(maskedBytes, 3)) + 1n) * magicNum) & U32Max);
+                ctx = norderHash(prevBytes & bitMask, magicNum);
 
                 bitCtx = 1;
             }
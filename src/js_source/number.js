@@ -0,0 +1,85 @@
+// Predicts numeric literals in text from the digit position, decimal point
+// and exponent and the shape of the previous number. Predicts nothing outside numbers.
+let NumberModel = (hashMap, bitHistory) => {
+    let contexts = MixedContexts(hashMap, bitHistory, 3);
+    let inNumber = false;
+    let intDigits = 0, fracDigits = 0, hasPoint = 0;
+    // 0: no exponent, 1: after e, 2: after the exponent sign, 3: in exponent digits
+    let expState = 0, expDigits = 0;
+    // Byte preceding the number, e.g. - or ,
+    let startByte = 0;
+    let prevShape = 0;
+    let prevBytes = 0;
+    let bitCtx = 1;
+    let magicNum = hash(0x9e30n, 2);
+    if (bitHistory) {
+        magicNum = hash((magicNum + 1n) & U32Max, 2);
+    }
+
+    let isDigit = (byte) => byte >= 48 && byte <= 57;
+    let shape = () => Math.min(intDigits, 15)
+        | (Math.min(fracDigits, 15) << 4)
+        | (hasPoint << 8)
+        | (expState << 9)
+        | (Math.min(expDigits, 3) << 11);
+
+    let update = (byte) => {
+        let prevByte = prevBytes & 0xff;
+        prevBytes = ((prevBytes << 8) | byte) >>> 0;
+        let char = String.fromCharCode(byte);
+
+        if (inNumber) {
+            if (isDigit(byte) && expState) {
+                expState = 3;
+                expDigits++;
+            } else if (isDigit(byte) && hasPoint) {
+                fracDigits++;
+            } else if (isDigit(byte)) {
+                intDigits++;
+            } else if (char == '.' && !hasPoint && !expState) {
+                hasPoint = 1;
+            } else if ((char == 'e' || char == 'E') && !expState) {
+                expState = 1;
+            } else if ((char == '+' || char == '-') && expState == 1) {
+                expState = 2;
+            } else {
+                prevShape = shape();
+                inNumber = false;
+            }
+        // Digits of identifiers like vec3 aren't numbers
+        } else if (isDigit(byte) && !/[a-zA-Z0-9_$]/.test(String.fromCharCode(prevByte))) {
+            // Numbers like .5 start with the decimal point
+            hasPoint = +(prevByte == 46);
+            inNumber = true;
+            intDigits = 1 - hasPoint;
+            fracDigits = hasPoint;
+            expState = 0;
+            expDigits = 0;
+            startByte = (prevBytes >>> (8 + 8 * hasPoint)) & 0xff;
+        }
+    };
+
+    return {
+        pred: () => inNumber ? contexts.pred(bitCtx) : 0,
+        learn: (bit) => {
+            if (inNumber) {
+                contexts.learn(bitCtx, bit);
+            }
+
+            bitCtx = (bitCtx << 1) | bit;
+            if (bitCtx >= 256) {
+                update(bitCtx & 0xff);
+                if (inNumber) {
+                    let s = shape();
+                    contexts.setCtxs([
+                        (s | (prevShape << 16)) >>> 0,
+                        (s | ((prevBytes & 0xffff) << 16)) >>> 0,
+                        (s | (startByte << 16) | ((prevBytes & 0xff) << 24)) >>> 0,
+                    ], 0, magicNum);
+                }
+
+                bitCtx = 1;
+            }
+        },
+    };
+};
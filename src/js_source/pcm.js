@@ -0,0 +1,11 @@
+// Splits planar PCM (8 bit unsigned or 16 bit signed little-endian) into a Float32Array
+// of samples in [-1, 1) per channel, with the options `new AudioBuffer` takes
+let pcm = (data, sampleRate, numberOfChannels, bits) => {
+    let samples = bits > 8 ? new Int16Array(data.buffer) : data;
+    let length = samples.length / numberOfChannels;
+    return {
+        sampleRate, numberOfChannels, length,
+        channels: [...Array(numberOfChannels)].map((_, c) =>
+            Float32Array.from(samples.subarray(c * length, c * length + length), x => bits > 8 ? x / 32768 : x / 128 - 1))
+    };
+};
@@ -0,0 +1,93 @@
+// Uses the bytes at the same column of the previous one or two records
+// and the column as context. A length of 0 detects the record length
+// from distances that repeat between occurrences of the same byte, starting
+// over at each of the sorted fileStarts.
+let Record = (length, records, fileStarts, hashMap, bitHistory) => {
+    let MAX_LENGTH = 4096;
+    let MIN_VOTES = 8;
+    let ctx = 0;
+    let bitCtx = 1;
+    let isAuto = !length;
+    let history = new Uint8Array(2 * MAX_LENGTH);
+    let historyAt = (pos) => history[pos & (history.length - 1)];
+    let pos = 0;
+    let bytePositions = Array.from({ length: 256 }, () => [0, 0, 0]);
+    let candidateLength = 0;
+    let votes = 0;
+    // Index of the next file start
+    let nextFile = 0;
+    let magicNum = hash(BigInt(0x2e00 | records), 2);
+    let slots = ContextSlots(hashMap, bitHistory);
+    if (bitHistory) {
+        magicNum = hash((magicNum + 1n) & U32Max, 2);
+    }
+
+    let detectLength = (byte) => {
+        let [p1, p2, p3] = bytePositions[byte];
+        let distance = pos - p1;
+        if (distance >= 2 && distance <= MAX_LENGTH && distance == p1 - p2 && distance == p2 - p3) {
+            if (distance == candidateLength) {
+                votes = Math.min(votes + 1, 2 * MIN_VOTES);
+            } else if (!votes) {
+                candidateLength = distance;
+                votes = 1;
+            } else {
+                votes--;
+            }
+
+            if (votes >= MIN_VOTES) {
+                length = candidateLength;
+            }
+        }
+        bytePositions[byte] = [pos, p1, p2];
+    };
+
+    // Forgets the detected length when a new file starts at pos
+    let startFile = () => {
+        let isFileStart = 0;
+        for (; nextFile < fileStarts.length && fileStarts[nextFile] <= pos; nextFile++) {
+            isFileStart |= fileStarts[nextFile] == pos;
+        }
+
+        if (isFileStart) {
+            bytePositions = Array.from({ length: 256 }, () => [0, 0, 0]);
+            candidateLength = 0;
+            votes = 0;
+            length = 0;
+        }
+    };
+
+    return {
+        pred: () => slots.pred(ctx ^ bitCtx),
+        learn: (bit) => {
+            slots.learn(ctx ^ bitCtx, bit);
+
+            bitCtx = (bitCtx << 1) | bit;
+            if (bitCtx >= 256) {
+                let currentByte = bitCtx & 0xff;
+
+                if (isAuto) {
+                    detectLength(currentByte);
+                }
+                history[pos & (history.length - 1)] = currentByte;
+                pos++;
+                if (isAuto) {
+                    startFile();
+                }
+
+                let column = 0;
+                let above = 0;
+                if (length) {
+                    column = pos % length;
+                    above = historyAt(pos - length);
+                    if (records == 2) {
+                        above |= historyAt(pos - 2 * length) << 8;
+                    }
+                }
+                ctx = Number(((hash(BigInt(column | (length << 13)), 3) * 9n + hash(BigInt(above), 3) + 1n) * magicNum) & U32Max);
+
+                bitCtx = 1;
+            }
+        },
+    };
+};
@@ -0,0 +1,6 @@
+// Undoes the transpose filter of data[start, end) in place, trailing bytes stay as they are
+let untranspose = (data, start, end, stride) => {
+    let rows = (end - start) / stride | 0;
+    let planes = data.slice(start, start + rows * stride);
+    for (let i = 0; i < rows * stride; ++i) data[start + i] = planes[i % stride * rows + (i / stride | 0)];
+};
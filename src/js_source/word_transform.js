@@ -0,0 +1,18 @@
+// Expands the words of data[start, end) that were replaced with single byte codes
+let unwords = (data, start, end, codes, words) => {
+    words = words.split(' ');
+    let expanded = [];
+    for (let i = start; i < end; ++i) {
+        let idx = codes.indexOf(data[i]);
+        if (idx < 0) {
+            expanded.push(data[i]);
+        } else {
+            for (let c of words[idx]) expanded.push(c.charCodeAt());
+        }
+    }
+    let out = new Uint8Array(data.length - (end - start) + expanded.length);
+    out.set(data.subarray(0, start));
+    out.set(expanded, start);
+    out.set(data.subarray(end), start + expanded.length);
+    return out;
+};
@@ -1,32 +1,44 @@
 use std::{
-    cell::RefCell,
+    collections::BTreeMap,
     fs::File,
     io::Read,
     path::{Path, PathBuf},
-    rc::Rc,
 };
 
 use anyhow::{bail, Context, Result};
 use clap::Parser;
 use compressor::Encoder;
 use human_panic::{setup_panic, Metadata};
-use model::{HashTable, NOrderByteData};
 use output_generator::{render_output, OutputGenerationOptions};
 use tracing_subscriber::{fmt, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 
 use crate::{
-    model_finder::create_default_model_config, output_generator::BundledFile,
+    compress_config::{
+        create_hash_tables, CompressConfig, CounterType, FileRanges, HashTableLayouts,
+        HashTableSize, ModelConfig,
+    },
+    filter::Filter,
+    glsl_minifier::SHADER_EXTENSIONS,
+    model::Model,
+    output_generator::{BundledFile, DecodedFile},
     report::ReportGenerator,
+    word_transform::WordTransform,
 };
 
 mod coder;
 mod compress_config;
 mod compressor;
+mod filter;
+mod glsl_minifier;
+mod js_renamer;
 mod model;
 mod model_finder;
 mod output_generator;
+mod png_decoder;
 mod report;
 mod utils;
+mod wav_decoder;
+mod word_transform;
 
 /// Command-line arguments
 #[derive(Parser, Debug)]
@@ -37,7 +49,8 @@ struct Cli {
     js_main: String,
 
     /// Files to be included and packed into the output, with compression.
-    /// Order matters, so files of similar content should be ordered together.
+    /// Order matters, so files of similar content should be ordered together,
+    /// see --optimize-order.
     #[arg(short, long, value_delimiter = ',')]
     files: Vec<String>,
 
@@ -56,6 +69,74 @@ struct Cli {
     /// If set, reports detailed compression statistics to websqz-report.html
     #[arg(short, long)]
     report: bool,
+
+    /// JSON file with the compression config (model and hash table size).
+    /// The built-in model is used if not set.
+    #[arg(short, long)]
+    config: Option<String>,
+
+    /// Log2 of the number of hash table entries (4 bytes each),
+    /// or 'auto' to derive it from the input size. Overrides the config.
+    #[arg(long)]
+    hash_table_size: Option<HashTableSize>,
+
+    /// Verify hash table entries with a check byte, so unrelated contexts
+    /// replace each other instead of sharing statistics
+    #[arg(long)]
+    checked_hash_table: bool,
+
+    /// Warm up the model with the minified decompressor source, which the
+    /// decoder already has, so it starts out with some JS vocabulary
+    #[arg(long)]
+    prime_decompressor: bool,
+
+    /// Text files (base names of the JS main or files) to replace frequent
+    /// long words in with single byte codes before compressing
+    #[arg(long, value_delimiter = ',')]
+    word_replace: Vec<String>,
+
+    /// Strip comments and whitespace from .glsl, .frag and .vert files and
+    /// shorten their local identifiers. Uniforms, ins and outs keep their names.
+    #[arg(long)]
+    minify_glsl: bool,
+
+    /// Decode the .png files to RGBA pixels, which compress much better than
+    /// the deflated PNG data. They are available as `ImageData` in `wsqz.images`.
+    #[arg(long)]
+    decode_png: bool,
+
+    /// Decode the .wav files (8 or 16 bit PCM) to planar samples, one channel after the other.
+    /// They are available as `Float32Array`s per channel in `wsqz.sounds`.
+    #[arg(long)]
+    decode_wav: bool,
+
+    /// Rename the locals of the JS main to the names the model compresses best.
+    /// Tries other names for the given number of most frequent locals,
+    /// each try compresses the JS main once.
+    #[arg(long)]
+    rename_js: Option<usize>,
+
+    /// Search the order of the files that compresses best, by inserting them one by one
+    /// where the input compresses best so far. Compresses the input about n²/2 times.
+    #[arg(long)]
+    optimize_order: bool,
+
+    /// Compress each of the files whichever way is smallest: with the model,
+    /// with deflate, which the browser inflates, or not at all like the pre-compressed files.
+    /// Compresses the input once more per file.
+    #[arg(long)]
+    auto: bool,
+
+    /// Reversible filter for a file (base name of the JS main or a file),
+    /// e.g. 'vertices.bin=delta:4' or 'texture.raw=transpose:4'.
+    /// Repeat to chain filters, they are applied in the given order.
+    #[arg(long = "filter", value_parser = parse_file_filter)]
+    filters: Vec<(String, Filter)>,
+
+    /// Text file to warm up the model with, e.g. common WebGL identifiers.
+    /// It is embedded into the bootstrap, so it should be small.
+    #[arg(long)]
+    prime_with: Option<String>,
 }
 
 fn main() -> Result<()> {
@@ -74,53 +155,338 @@ fn main() -> Result<()> {
     if args.js_main.is_empty() {
         bail!("No JS main file specified");
     }
+    check_file_names(std::iter::once(&args.js_main).chain(&args.files))?;
 
-    let model_config = create_default_model_config();
+    let mut config = match &args.config {
+        Some(config_path) => serde_json::from_reader::<_, CompressConfig>(
+            File::open(config_path)
+                .context(format!("Failed to open config file: {}", config_path))?,
+        )
+        .context(format!("Failed to parse config file: {}", config_path))?,
+        None => CompressConfig::default(),
+    };
+    if let Some(hash_table_size) = args.hash_table_size {
+        config.hash_table_size = hash_table_size;
+    }
+    config.checked_hash_table |= args.checked_hash_table;
+    config.prime_decompressor |= args.prime_decompressor;
+    config.minify_glsl |= args.minify_glsl;
+    config.decode_png |= args.decode_png;
+    config.decode_wav |= args.decode_wav;
+    config.optimize_order |= args.optimize_order;
+    config.auto |= args.auto;
+    if let Some(max_locals) = args.rename_js {
+        config.rename_js = Some(max_locals);
+    }
+    config.word_replace.extend(args.word_replace);
+    for (file, filter) in args.filters {
+        config.filters.entry(file).or_default().push(filter);
+    }
+    if let Some(prime_with) = args.prime_with {
+        config.prime_with = Some(prime_with);
+    }
 
     println!(
         "Starting compression (websqz v{})",
         env!("CARGO_PKG_VERSION")
     );
-    println!("Initializing hash table...");
-    let model = model_config
-        .create_model(Rc::new(RefCell::new(HashTable::<NOrderByteData>::new(26))))
-        .context("Failed to create model from config")?;
 
     let mut main_js_bytes = Vec::new();
     File::open(&args.js_main)
         .context(format!("Failed to open JS main file: {}", args.js_main))?
         .read_to_end(&mut main_js_bytes)?;
 
-    let mut encoded_data: Vec<u8> = Vec::new();
-    let mut encoder = Encoder::new(model, &mut encoded_data)?;
-
-    println!("Compressing input data ({} bytes)", main_js_bytes.len());
-    encoder.encode_section(main_js_bytes.as_slice())?;
+    if let Some(max_locals) = config.rename_js {
+        println!("Renaming locals of the JS main...");
+        let file_names = args
+            .files
+            .iter()
+            .map(|file| file_name(file))
+            .collect::<Result<Vec<_>>>()?;
+        let renamed = rename_js_main(
+            &config,
+            &file_name(&args.js_main)?,
+            &file_names,
+            &main_js_bytes,
+            max_locals,
+        );
+        match renamed {
+            Ok(renamed) => {
+                println!(
+                    "Renamed {} locals of the JS main, which saves about {:.0} bytes of compressed data",
+                    renamed.renamed,
+                    (renamed.bits_before - renamed.bits_after) / 8.
+                );
+                main_js_bytes = renamed.source.into_bytes();
+            }
+            Err(err) => println!("Keeping the names of the JS main: {:#}", err),
+        }
+    }
 
+    // The JS main and the extra files, by base name like in `wsqz.files`
+    let mut inputs = vec![(file_name(&args.js_main)?, main_js_bytes.clone())];
     let mut bundled_files = Vec::new();
+    let mut shader_maps = Vec::new();
     let mut offset = main_js_bytes.len() as u32;
     for file in &args.files {
-        let mut byte_stream =
-            File::open(file).context(format!("Failed to open additional file: {}", file))?;
-        let file_len = byte_stream.metadata()?.len() as u32;
-        println!("Compressing additional file ({} bytes): {}", file_len, file);
-        encoder.encode_section(&mut byte_stream)?;
-
+        let loaded = load_file(&mut config, file)?;
+        let content = loaded.content;
+        if let Some(renames) = loaded.shader_renames {
+            shader_maps.push((file_name(file)?, renames));
+        }
         bundled_files.push(BundledFile {
             path: PathBuf::from(file),
             start_offset: offset,
-            length: file_len,
+            length: content.len() as u32,
+            decoded: loaded.decoded,
         });
+        offset += content.len() as u32;
+        inputs.push((file_name(file)?, content));
+    }
+
+    for name in &config.word_replace {
+        if !inputs.iter().any(|(input_name, _)| input_name == name) {
+            bail!(
+                "File '{}' to replace words in is not part of the input",
+                name
+            );
+        }
+    }
+    for name in config.filters.keys() {
+        if !inputs.iter().any(|(input_name, _)| input_name == name) {
+            bail!("File '{}' to filter is not part of the input", name);
+        }
+    }
+    for (name, content) in &mut inputs {
+        if let Some(filters) = config.filters.get(name) {
+            println!(
+                "Filtering '{}' with {}",
+                name,
+                filters
+                    .iter()
+                    .map(Filter::to_string)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            for filter in filters {
+                *content = filter.apply(content);
+            }
+        }
+    }
+    let mut word_transforms: Vec<Option<WordTransform>> = inputs
+        .iter()
+        .map(|(name, content)| {
+            if !config.word_replace.contains(name) {
+                return None;
+            }
+            let transform = WordTransform::build(content);
+            if transform.is_none() {
+                println!("No words worth replacing in '{}'", name);
+            }
+            transform
+        })
+        .collect();
+    let input_len = transform_sections(&inputs, &word_transforms)
+        .0
+        .iter()
+        .map(Vec::len)
+        .sum();
 
-        offset += file_len;
+    let dictionary = config
+        .prime_with
+        .as_ref()
+        .map(|path| {
+            std::fs::read_to_string(path).context(format!(
+                "Failed to read priming dictionary (UTF-8 text): {}",
+                path
+            ))
+        })
+        .transpose()?;
+    let dictionary_len = dictionary.as_ref().map_or(0, |dictionary| dictionary.len());
+
+    let mut hash_table_layouts = config.hash_table_layouts(input_len)?;
+
+    // Files placed by `--auto` outside of the model, stored or deflated
+    let mut placed_files = Vec::new();
+    if config.auto && inputs.len() > 1 {
+        placed_files = place_files(
+            &config,
+            &hash_table_layouts,
+            &mut inputs,
+            &mut word_transforms,
+            &mut bundled_files,
+            dictionary.as_deref(),
+        )?;
+        let input_len = transform_sections(&inputs, &word_transforms)
+            .0
+            .iter()
+            .map(Vec::len)
+            .sum();
+        hash_table_layouts = config.hash_table_layouts(input_len)?;
     }
 
-    let size_before_compression = encoder.finish().context("Failed to finish compressing")?;
+    if config.optimize_order && inputs.len() > 2 {
+        println!("Optimizing the order of {} files...", inputs.len() - 1);
+        let encoded_len_in = |order: &[usize]| {
+            encoded_len(
+                &config,
+                &hash_table_layouts,
+                &inputs,
+                &word_transforms,
+                order,
+                dictionary.as_deref(),
+            )
+        };
+        let given_len = encoded_len_in(&(0..inputs.len()).collect::<Vec<_>>())?;
+        let (order, best_len) = optimize_order(inputs.len(), encoded_len_in)?;
+        if best_len < given_len {
+            println!(
+                "Best file order saves {} bytes of compressed data: --files {}",
+                given_len - best_len,
+                order[1..]
+                    .iter()
+                    .map(|&idx| bundled_files[idx - 1].path.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            );
+            inputs = reorder(inputs, &order);
+            word_transforms = reorder(word_transforms, &order);
+            bundled_files = reorder(
+                bundled_files,
+                &order[1..].iter().map(|idx| idx - 1).collect::<Vec<_>>(),
+            );
+            set_start_offsets(&mut bundled_files, inputs[0].1.len() as u32);
+        } else {
+            println!("The given file order compresses best");
+        }
+    }
+
+    // Filters keep the length, so the ranges of the files stay the same
+    let mut filter_ranges = Vec::new();
+    let mut offset = 0;
+    for (name, content) in &inputs {
+        let len = content.len() as u32;
+        if let Some(filters) = config.filters.get(name) {
+            filter_ranges.push((offset..offset + len, filters.clone()));
+        }
+        offset += len;
+    }
+    let (sections, file_ranges) = transform_sections(&inputs, &word_transforms);
+
+    let mut model_config = config.model.clone();
+    let prime_source = resolve_files(
+        &mut model_config,
+        &file_ranges,
+        &hash_table_layouts,
+        config.prime_decompressor,
+        dictionary_len as u32,
+    )?;
+
+    println!("Initializing hash table...");
+    let hash_tables = create_hash_tables(&hash_table_layouts);
+    let model = model_config
+        .create_model(&hash_tables)
+        .context("Failed to create model from config")?;
+
+    if let Some(source) = &prime_source {
+        println!(
+            "Warming up with the decompressor source ({} bytes)",
+            source.len()
+        );
+    }
+    if dictionary.is_some() {
+        println!(
+            "Warming up with the priming dictionary ({} bytes)",
+            dictionary_len
+        );
+    }
+    println!("Compressing input data ({} bytes)", main_js_bytes.len());
+    for file in &bundled_files {
+        println!(
+            "Compressing additional file ({} bytes): {}",
+            file.length,
+            file.path.display()
+        );
+    }
+
+    let warm_up = [prime_source.as_deref(), dictionary.as_deref()];
+    let (encoded_data, size_before_compression) = encode(model, &warm_up, &sections)?;
     println!(
         "Finished compressing input data ({} bytes)",
         encoded_data.len()
     );
 
+    // Compresses the input again without some of the options, to report what they save
+    let all_inputs: Vec<usize> = (0..inputs.len()).collect();
+    let encoded_len_with = |dictionary: Option<&str>, word_transforms: &[Option<WordTransform>]| {
+        encoded_len(
+            &config,
+            &hash_table_layouts,
+            &inputs,
+            word_transforms,
+            &all_inputs,
+            dictionary,
+        )
+    };
+
+    if prime_source.is_some() {
+        let unprimed = CompressConfig {
+            prime_decompressor: false,
+            ..config.clone()
+        };
+        println!(
+            "Priming with the decompressor source saves {} bytes of compressed data",
+            encoded_len(
+                &unprimed,
+                &hash_table_layouts,
+                &inputs,
+                &word_transforms,
+                &all_inputs,
+                dictionary.as_deref(),
+            )? - encoded_data.len() as i64
+        );
+    }
+
+    if dictionary.is_some() {
+        println!(
+            "Priming dictionary saves {} bytes of compressed data",
+            encoded_len_with(None, &word_transforms)? - encoded_data.len() as i64
+        );
+    }
+
+    for (idx, transform) in word_transforms.iter().enumerate() {
+        let Some(transform) = transform else {
+            continue;
+        };
+
+        let mut without_transform = word_transforms.clone();
+        without_transform[idx] = None;
+        let saved = encoded_len_with(dictionary.as_deref(), &without_transform)?
+            - encoded_data.len() as i64;
+        let dictionary_len = transform.dictionary_len() as i64;
+        println!(
+            "Word replacement in '{}' ({} words) saves {} bytes of compressed data for a {} byte dictionary, {}",
+            inputs[idx].0,
+            transform.words.len(),
+            saved,
+            dictionary_len,
+            if saved > dictionary_len { "keep it enabled" } else { "better disable it" }
+        );
+    }
+
+    for (name, hash_table) in &hash_tables {
+        let hash_table = hash_table.borrow();
+        if hash_table.is_checked() {
+            println!(
+                "Hash table '{}' collisions: {} of {} insertions ({:.2}%)",
+                name.as_deref().unwrap_or("shared"),
+                hash_table.collisions,
+                hash_table.insertions,
+                100. * hash_table.collisions as f64 / hash_table.insertions.max(1) as f64
+            );
+        }
+    }
+
     let pre_compressed_files: Result<Vec<output_generator::FileWithContent>> = args
         .pre_compressed_files
         .into_iter()
@@ -130,8 +496,10 @@ fn main() -> Result<()> {
             return Ok(output_generator::FileWithContent {
                 path: PathBuf::from(&path),
                 content,
+                deflated: false,
             });
         })
+        .chain(placed_files.into_iter().map(Ok))
         .collect();
 
     println!("Rendering output...");
@@ -141,6 +509,15 @@ fn main() -> Result<()> {
             output_dir: Path::new(&args.output_directory).to_owned(),
             target: args.target,
             model_config: model_config.clone(),
+            hash_tables: hash_table_layouts.clone(),
+            prime_source,
+            dictionary,
+            word_transforms: word_transforms
+                .into_iter()
+                .zip(&inputs)
+                .filter_map(|(transform, (name, _))| Some((file_ranges[name].clone(), transform?)))
+                .collect(),
+            filters: filter_ranges,
         },
         size_before_compression,
         encoded_data,
@@ -150,10 +527,26 @@ fn main() -> Result<()> {
     )
     .context("Failed to render output")?;
 
+    if !shader_maps.is_empty() {
+        let map_path = Path::new(&args.output_directory).join("shader-map.txt");
+        let mut map = String::new();
+        for (name, renames) in &shader_maps {
+            map += &format!("{}\n", name);
+            for (old_name, new_name) in renames {
+                map += &format!("    {} -> {}\n", old_name, new_name);
+            }
+        }
+        std::fs::write(&map_path, map).context("Failed to write shader map")?;
+        println!(
+            "Renamed shader identifiers written to '{}'",
+            map_path.display()
+        );
+    }
+
     if args.report {
         println!("Generating compression report...");
         let model = model_config
-            .create_model(Rc::new(RefCell::new(HashTable::<NOrderByteData>::new(26))))
+            .create_model(&create_hash_tables(&hash_table_layouts))
             .context("Failed to create model from config")?;
 
         ReportGenerator::create(
@@ -177,8 +570,419 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Parses a `<file>=<filter>` command line argument
+fn parse_file_filter(arg: &str) -> Result<(String, Filter)> {
+    let (file, filter) = arg
+        .split_once('=')
+        .context("Expected <file>=<filter>, e.g. 'vertices.bin=delta:4'")?;
+    Ok((file.to_owned(), filter.parse()?))
+}
+
+/// An input file after minifying and decoding
+struct LoadedFile {
+    content: Vec<u8>,
+    decoded: Option<DecodedFile>,
+    /// Original and new names if the file is a minified shader
+    shader_renames: Option<Vec<(String, String)>>,
+}
+
+/// Reads an extra input file and minifies or decodes it as configured.
+/// Decoded images and sounds get a model of their format added to the config,
+/// unless it already has an `InFile` model for them.
+fn load_file(config: &mut CompressConfig, file: &str) -> Result<LoadedFile> {
+    let mut content =
+        std::fs::read(file).context(format!("Failed to open additional file: {}", file))?;
+    let mut shader_renames = None;
+    let is_shader = Path::new(file)
+        .extension()
+        .is_some_and(|extension| SHADER_EXTENSIONS.iter().any(|ext| extension == *ext));
+    if config.minify_glsl && is_shader {
+        let minified = glsl_minifier::minify(
+            std::str::from_utf8(&content).context(format!("Shader is not UTF-8: {}", file))?,
+        )
+        .context(format!("Failed to minify shader: {}", file))?;
+        println!(
+            "Minified shader from {} to {} bytes, renamed {} identifiers: {}",
+            content.len(),
+            minified.source.len(),
+            minified.renames.len(),
+            file
+        );
+        content = minified.source.into_bytes();
+        shader_renames = Some(minified.renames);
+    }
+    let has_extension = |wanted: &str| {
+        Path::new(file)
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case(wanted))
+    };
+    let mut decoded = None;
+    if config.decode_png && has_extension("png") {
+        let image =
+            png_decoder::decode_png(&content).context(format!("Failed to decode PNG: {}", file))?;
+        println!(
+            "Decoded PNG of {} bytes to {}x{} RGBA pixels ({} bytes): {}",
+            content.len(),
+            image.width,
+            image.height,
+            image.pixels.len(),
+            file
+        );
+        let name = file_name(file)?;
+        if config.model.in_files().contains(&name.as_str()) {
+            println!("  Modeling it with the InFile model of the config");
+        } else {
+            println!("  Modeling it as a {} pixel wide RGBA image", image.width);
+            config.model.add_to_mixer(ModelConfig::InFile {
+                file: name,
+                model: Box::new(ModelConfig::Image {
+                    width: image.width,
+                    channels: 4,
+                    bytes_per_channel: 1,
+                    table: None,
+                    counter: CounterType::Probability,
+                }),
+                range: None,
+            });
+        }
+        content = image.pixels;
+        decoded = Some(DecodedFile::Image {
+            width: image.width,
+            height: image.height,
+        });
+    }
+    if config.decode_wav && has_extension("wav") {
+        let wav =
+            wav_decoder::decode_wav(&content).context(format!("Failed to decode WAV: {}", file))?;
+        println!(
+            "Decoded WAV of {} bytes to {} channels of {} bit PCM at {} Hz ({} bytes): {}",
+            content.len(),
+            wav.channels,
+            wav.bits_per_sample,
+            wav.sample_rate,
+            wav.samples.len(),
+            file
+        );
+        let name = file_name(file)?;
+        if config.model.in_files().contains(&name.as_str()) {
+            println!("  Modeling it with the InFile model of the config");
+        } else {
+            println!("  Modeling it as {} bit audio", wav.bits_per_sample);
+            // The channels are planar, so the model sees one channel at a time
+            config.model.add_to_mixer(ModelConfig::InFile {
+                file: name,
+                model: Box::new(ModelConfig::Audio {
+                    bits_per_sample: wav.bits_per_sample,
+                    channels: 1,
+                    table: None,
+                    counter: CounterType::Probability,
+                }),
+                range: None,
+            });
+        }
+        content = wav.samples;
+        decoded = Some(DecodedFile::Pcm {
+            sample_rate: wav.sample_rate,
+            channels: wav.channels,
+            bits_per_sample: wav.bits_per_sample,
+        });
+    }
+    Ok(LoadedFile {
+        content,
+        decoded,
+        shader_renames,
+    })
+}
+
+/// Chooses for `--auto` how to store each file other than the JS main: with the model,
+/// deflated or stored. Removes the files placed outside of the model from the inputs
+/// and returns them.
+fn place_files(
+    config: &CompressConfig,
+    hash_table_layouts: &HashTableLayouts,
+    inputs: &mut Vec<(String, Vec<u8>)>,
+    word_transforms: &mut Vec<Option<WordTransform>>,
+    bundled_files: &mut Vec<BundledFile>,
+    dictionary: Option<&str>,
+) -> Result<Vec<output_generator::FileWithContent>> {
+    println!("Choosing how to store each file...");
+    let in_files = config.model.in_files();
+    let all_inputs: Vec<usize> = (0..inputs.len()).collect();
+    let encoded_len_without = |left_out: Option<usize>| {
+        let order: Vec<usize> = all_inputs
+            .iter()
+            .copied()
+            .filter(|&idx| Some(idx) != left_out)
+            .collect();
+        encoded_len(
+            config,
+            hash_table_layouts,
+            inputs,
+            word_transforms,
+            &order,
+            dictionary,
+        )
+    };
+    let full_len = encoded_len_without(None)?;
+
+    // The first deflated file also brings the inflate helper into the boot script
+    let mut inflate_helper_len = output_generator::inflate_helper_len()? as i64;
+    println!(
+        "The inflate helper costs {} bytes, counted with the first deflated file",
+        inflate_helper_len
+    );
+    let mut placed_files = Vec::new();
+    let mut moved = Vec::new();
+    for idx in 1..inputs.len() {
+        let (name, content) = &inputs[idx];
+        // These only work with the model
+        if bundled_files[idx - 1].decoded.is_some()
+            || config.filters.contains_key(name)
+            || word_transforms[idx].is_some()
+            || in_files.contains(&name.as_str())
+        {
+            println!("'{}': kept with the model", name);
+            continue;
+        }
+
+        let model_len = full_len - encoded_len_without(Some(idx))?;
+        let deflated = output_generator::deflate(content)?;
+        let stored_len = content.len() as i64;
+        let deflated_len = deflated.len() as i64 + inflate_helper_len;
+        let (choice, file) = if model_len <= deflated_len && model_len <= stored_len {
+            ("with the model", None)
+        } else if deflated_len < stored_len {
+            inflate_helper_len = 0;
+            ("deflated", Some((deflated, true)))
+        } else {
+            ("stored", Some((content.clone(), false)))
+        };
+        println!(
+            "'{}': {} bytes with the model, {} deflated, {} stored, {}",
+            name, model_len, deflated_len, stored_len, choice
+        );
+        if let Some((content, deflated)) = file {
+            moved.push(idx);
+            placed_files.push(output_generator::FileWithContent {
+                path: bundled_files[idx - 1].path.clone(),
+                content,
+                deflated,
+            });
+        }
+    }
+
+    for &idx in moved.iter().rev() {
+        inputs.remove(idx);
+        word_transforms.remove(idx);
+        bundled_files.remove(idx - 1);
+    }
+    set_start_offsets(bundled_files, inputs[0].1.len() as u32);
+    Ok(placed_files)
+}
+
+/// Lays the bundled files out one after another behind the JS main
+fn set_start_offsets(bundled_files: &mut [BundledFile], main_len: u32) {
+    let mut offset = main_len;
+    for file in bundled_files {
+        file.start_offset = offset;
+        offset += file.length;
+    }
+}
+
+/// Renames the locals of the JS main to the names the model codes cheapest.
+/// Only the JS main is coded for the cost, so `InFile` models of the other files don't apply.
+fn rename_js_main(
+    config: &CompressConfig,
+    main_name: &str,
+    file_names: &[String],
+    main_js_bytes: &[u8],
+    max_locals: usize,
+) -> Result<js_renamer::RenamedSource> {
+    let mut file_ranges: FileRanges = file_names.iter().map(|name| (name.clone(), 0..0)).collect();
+    file_ranges.insert(main_name.to_owned(), 0..u32::MAX);
+    let mut model_config = config.model.clone();
+    model_config.resolve_files(&file_ranges)?;
+    let hash_table_layouts = config.hash_table_layouts(main_js_bytes.len())?;
+    let create_model = || model_config.create_model(&create_hash_tables(&hash_table_layouts));
+
+    let source = std::str::from_utf8(main_js_bytes).context("JS main is not UTF-8")?;
+    js_renamer::rename_locals(source, &create_model, max_locals)
+}
+
+/// Applies the word transforms to the inputs, returns the sections to compress
+/// and the ranges of the files in the decoded data
+fn transform_sections(
+    inputs: &[(String, Vec<u8>)],
+    word_transforms: &[Option<WordTransform>],
+) -> (Vec<Vec<u8>>, FileRanges) {
+    let mut sections = Vec::new();
+    let mut file_ranges = FileRanges::new();
+    let mut offset = 0;
+    for ((name, content), transform) in inputs.iter().zip(word_transforms) {
+        let section = match transform {
+            Some(transform) => transform.apply(content),
+            None => content.clone(),
+        };
+        file_ranges.insert(name.clone(), offset..offset + section.len() as u32);
+        offset += section.len() as u32;
+        sections.push(section);
+    }
+
+    (sections, file_ranges)
+}
+
+/// Compressed size of the inputs in `order`, the inputs missing in it are left out
+fn encoded_len(
+    config: &CompressConfig,
+    hash_table_layouts: &HashTableLayouts,
+    inputs: &[(String, Vec<u8>)],
+    word_transforms: &[Option<WordTransform>],
+    order: &[usize],
+    dictionary: Option<&str>,
+) -> Result<i64> {
+    let (ordered_inputs, ordered_transforms): (Vec<_>, Vec<_>) = order
+        .iter()
+        .map(|&idx| (inputs[idx].clone(), word_transforms[idx].clone()))
+        .unzip();
+    let (sections, mut file_ranges) = transform_sections(&ordered_inputs, &ordered_transforms);
+    // `InFile` models of left out files apply to no bytes
+    for (name, _) in inputs {
+        file_ranges.entry(name.clone()).or_insert(0..0);
+    }
+
+    let mut model_config = config.model.clone();
+    let prime_source = resolve_files(
+        &mut model_config,
+        &file_ranges,
+        hash_table_layouts,
+        config.prime_decompressor,
+        dictionary.map_or(0, |dictionary| dictionary.len() as u32),
+    )?;
+    let model = model_config
+        .create_model(&create_hash_tables(hash_table_layouts))
+        .context("Failed to create model from config")?;
+    let (encoded_data, _) = encode(model, &[prime_source.as_deref(), dictionary], &sections)?;
+    Ok(encoded_data.len() as i64)
+}
+
+/// Finds an order of `count` inputs that compresses well by inserting them one by one
+/// where `encoded_len` of the inputs placed so far is smallest. The JS main stays first.
+/// Returns the order and its encoded length.
+fn optimize_order(
+    count: usize,
+    encoded_len: impl Fn(&[usize]) -> Result<i64>,
+) -> Result<(Vec<usize>, i64)> {
+    let mut order = vec![0];
+    let mut best_len = encoded_len(&order)?;
+    for idx in 1..count {
+        let mut best = None;
+        for pos in 1..=order.len() {
+            let mut candidate = order.clone();
+            candidate.insert(pos, idx);
+            let len = encoded_len(&candidate)?;
+            if best.as_ref().is_none_or(|(best_len, _)| len < *best_len) {
+                best = Some((len, candidate));
+            }
+        }
+        (best_len, order) = best.unwrap();
+    }
+    Ok((order, best_len))
+}
+
+/// Moves `items[order[i]]` to position i
+fn reorder<T>(items: Vec<T>, order: &[usize]) -> Vec<T> {
+    let mut items: Vec<Option<T>> = items.into_iter().map(Some).collect();
+    order
+        .iter()
+        .map(|&idx| items[idx].take().unwrap())
+        .collect()
+}
+
+/// Compresses the sections after warming up the model with the `warm_up` texts,
+/// returns the encoded data and the input size
+fn encode(
+    model: Box<dyn Model>,
+    warm_up: &[Option<&str>],
+    sections: &[Vec<u8>],
+) -> Result<(Vec<u8>, usize)> {
+    let mut encoded_data: Vec<u8> = Vec::new();
+    let mut encoder = Encoder::new(model, &mut encoded_data)?;
+    for text in warm_up.iter().flatten() {
+        encoder.warm_up(text.as_bytes())?;
+    }
+    for section in sections {
+        encoder.encode_section(section.as_slice())?;
+    }
+
+    let size_before_compression = encoder.finish().context("Failed to finish compressing")?;
+    Ok((encoded_data, size_before_compression))
+}
+
+/// Resolves the file ranges of `InFile` models and, if `prime` is set,
+/// returns the decompressor source to warm up the model with.
+/// `warm_up_len` is the length of the other warm-up texts.
+fn resolve_files(
+    model_config: &mut ModelConfig,
+    file_ranges: &FileRanges,
+    hash_tables: &HashTableLayouts,
+    prime: bool,
+    warm_up_len: u32,
+) -> Result<Option<String>> {
+    if !prime {
+        model_config.resolve_files(&shift_file_ranges(file_ranges, warm_up_len))?;
+        return Ok(None);
+    }
+
+    // The warm-up moves the input of `InFile` models by the length of the source,
+    // which depends on the ranges in it. Ranges only grow, so this settles quickly.
+    let mut source = String::new();
+    loop {
+        let offset = source.len() as u32 + warm_up_len;
+        model_config.resolve_files(&shift_file_ranges(file_ranges, offset))?;
+        let next = output_generator::prime_source(model_config, hash_tables)?;
+        if next.len() == source.len() {
+            return Ok(Some(next));
+        }
+        source = next;
+    }
+}
+
+/// Moves the file ranges `offset` bytes further into the input
+fn shift_file_ranges(file_ranges: &FileRanges, offset: u32) -> FileRanges {
+    file_ranges
+        .iter()
+        .map(|(name, range)| (name.clone(), range.start + offset..range.end + offset))
+        .collect()
+}
+
+/// Files are found by base name in `wsqz.files` and by `InFile` models,
+/// so two input files with the same base name can't be told apart
+fn check_file_names<'a>(paths: impl IntoIterator<Item = &'a String>) -> Result<()> {
+    let mut names = BTreeMap::new();
+    for path in paths {
+        if let Some(other) = names.insert(file_name(path)?, path) {
+            bail!(
+                "Input files '{}' and '{}' have the same name, rename one of them",
+                other,
+                path
+            );
+        }
+    }
+    Ok(())
+}
+
+fn file_name(path: &str) -> Result<String> {
+    Ok(Path::new(path)
+        .file_name()
+        .context("File name")?
+        .to_str()
+        .context("File name to str")?
+        .to_owned())
+}
+
 #[cfg(test)]
 mod node_tests {
+    use std::collections::BTreeMap;
     use std::path::PathBuf;
     use std::process::Command;
     use std::{cell::RefCell, fs::File, io::Read, path::Path, rc::Rc};
@@ -186,10 +990,21 @@ mod node_tests {
     use crate::model_finder::create_default_model_config;
     use crate::output_generator::{FileWithContent, OutputGenerationOptions};
     use crate::{
-        compress_config::CompressConfig,
+        check_file_names,
+        compress_config::{
+            create_hash_tables, ApmConfig, CompressConfig, CounterType, FileRanges,
+            HashTableConfig, HashTableLayout, HashTableLayouts, HashTableSize, HashTables,
+            ModelConfig,
+        },
         compressor::Encoder,
+        encode,
+        filter::Filter,
+        js_renamer,
         model::{HashTable, NOrderByteData},
-        output_generator::{self, render_output},
+        optimize_order,
+        output_generator::{self, render_output, BundledFile},
+        place_files, rename_js_main, resolve_files,
+        word_transform::WordTransform,
     };
 
     #[test]
@@ -202,7 +1017,10 @@ mod node_tests {
         let hash_table = HashTable::<NOrderByteData>::new(26);
         let model = model_config
             .model
-            .create_model(Rc::new(RefCell::new(hash_table)))
+            .create_model(&HashTables::from([(
+                None,
+                Rc::new(RefCell::new(hash_table)),
+            )]))
             .expect("Failed to create model from config");
 
         let mut input = String::new();
@@ -223,6 +1041,17 @@ mod node_tests {
                 output_dir: Path::new("testout/round_trip").to_owned(),
                 target: output_generator::Target::Node,
                 model_config: model_config.model,
+                hash_tables: HashTableLayouts::from([(
+                    None,
+                    HashTableLayout {
+                        pow2_size: 26,
+                        checked: false,
+                    },
+                )]),
+                prime_source: None,
+                dictionary: None,
+                word_transforms: vec![],
+                filters: vec![],
             },
             input_bytes.len(),
             encoded_data,
@@ -232,10 +1061,11 @@ mod node_tests {
         )
         .expect("Failed to render output");
 
-        Command::new("node")
+        let status = Command::new("node")
             .arg("testout/round_trip/index.mjs")
             .status()
             .expect("Failed to run node decompressor");
+        assert!(status.success(), "Node decompressor failed: {}", status);
 
         let output_path = Path::new("testout/round_trip/output.bin");
         let output_file = File::open(output_path).expect("Failed to open output.bin");
@@ -262,7 +1092,10 @@ mod node_tests {
         let hash_table = HashTable::<NOrderByteData>::new(26);
         let model = model_config
             .model
-            .create_model(Rc::new(RefCell::new(hash_table)))
+            .create_model(&HashTables::from([(
+                None,
+                Rc::new(RefCell::new(hash_table)),
+            )]))
             .expect("Failed to create model from config");
 
         let mut input = String::new();
@@ -285,6 +1118,17 @@ mod node_tests {
                 output_dir: Path::new("testout/web").to_owned(),
                 target: output_generator::Target::Web,
                 model_config: model_config.model,
+                hash_tables: HashTableLayouts::from([(
+                    None,
+                    HashTableLayout {
+                        pow2_size: 26,
+                        checked: false,
+                    },
+                )]),
+                prime_source: None,
+                dictionary: None,
+                word_transforms: vec![],
+                filters: vec![],
             },
             input_bytes.len(),
             encoded_data,
@@ -293,11 +1137,865 @@ mod node_tests {
             vec![FileWithContent {
                 path: PathBuf::from("Cargo.toml"),
                 content: std::fs::read("Cargo.toml").expect("Failed to read Cargo.toml"),
+                deflated: false,
             }],
         )
         .expect("Failed to render output");
     }
 
+    fn node_round_trip(mut config: CompressConfig, input_bytes: &[u8], output_dir: &str) {
+        let hash_tables = config.hash_table_layouts(input_bytes.len()).unwrap();
+        let dictionary = config
+            .prime_with
+            .as_ref()
+            .map(|path| std::fs::read_to_string(path).unwrap());
+        // Tests with `InFile` models resolve their files themselves
+        let prime_source = if config.prime_decompressor {
+            resolve_files(
+                &mut config.model,
+                &FileRanges::new(),
+                &hash_tables,
+                true,
+                dictionary
+                    .as_ref()
+                    .map_or(0, |dictionary| dictionary.len() as u32),
+            )
+            .unwrap()
+        } else {
+            None
+        };
+        let model = config
+            .model
+            .create_model(&create_hash_tables(&hash_tables))
+            .expect("Failed to create model from config");
+
+        // Any word replacement applies to the whole input
+        let word_transform = (!config.word_replace.is_empty())
+            .then(|| WordTransform::build(input_bytes).expect("No words to replace"));
+        // Like the word replacement, all filters apply to the whole input
+        let filters: Vec<Filter> = config.filters.values().flatten().copied().collect();
+        let filtered = filters
+            .iter()
+            .fold(input_bytes.to_vec(), |data, filter| filter.apply(&data));
+        let section = match &word_transform {
+            Some(transform) => transform.apply(&filtered),
+            None => filtered,
+        };
+
+        let (encoded_data, size_before_compression) = encode(
+            model,
+            &[prime_source.as_deref(), dictionary.as_deref()],
+            &[section],
+        )
+        .unwrap();
+
+        let output_dir = Path::new(output_dir);
+        render_output(
+            OutputGenerationOptions {
+                output_dir: output_dir.to_owned(),
+                target: output_generator::Target::Node,
+                model_config: config.model,
+                hash_tables,
+                prime_source,
+                dictionary,
+                word_transforms: word_transform
+                    .map(|transform| (0..size_before_compression as u32, transform))
+                    .into_iter()
+                    .collect(),
+                filters: if filters.is_empty() {
+                    vec![]
+                } else {
+                    vec![(0..input_bytes.len() as u32, filters)]
+                },
+            },
+            size_before_compression,
+            encoded_data,
+            input_bytes.len(),
+            vec![],
+            vec![],
+        )
+        .expect("Failed to render output");
+
+        let status = Command::new("node")
+            .arg(output_dir.join("index.mjs"))
+            .status()
+            .expect("Failed to run node decompressor");
+        assert!(status.success(), "Node decompressor failed: {}", status);
+
+        let output_data =
+            std::fs::read(output_dir.join("output.bin")).expect("Failed to read output.bin");
+
+        assert_eq!(
+            input_bytes,
+            output_data.as_slice(),
+            "Decompressed data does not match original input in {}",
+            output_dir.display()
+        );
+    }
+
+    #[test]
+    pub fn round_trip_apm_stages() {
+        let model_config = ModelConfig::AdaptiveProbabilityMap {
+            model: Box::new(ModelConfig::AdaptiveProbabilityMap {
+                model: Box::new(create_default_model_config()),
+                stages: vec![
+                    ApmConfig {
+                        pow2_size: 12,
+                        order: 0,
+                        rate: 30,
+                    },
+                    ApmConfig {
+                        pow2_size: 18,
+                        order: 2,
+                        rate: 10,
+                    },
+                ],
+            }),
+            stages: vec![ApmConfig {
+                pow2_size: 16,
+                order: 1,
+                rate: 50,
+            }],
+        };
+
+        let input = std::fs::read("tests/ray_tracer/index.js").unwrap();
+        node_round_trip(
+            CompressConfig {
+                model: model_config,
+                ..Default::default()
+            },
+            &input,
+            "testout/round_trip_apm",
+        );
+    }
+
+    #[test]
+    pub fn round_trip_checked_hash_table() {
+        let config = CompressConfig {
+            // Small enough to cause collisions
+            hash_table_size: HashTableSize::Pow2(16),
+            checked_hash_table: true,
+            ..Default::default()
+        };
+
+        let input = std::fs::read("tests/ray_tracer/index.js").unwrap();
+        node_round_trip(config, &input, "testout/round_trip_checked");
+    }
+
+    #[test]
+    pub fn round_trip_high_order_checked() {
+        // The check byte comes from the high bits of the context hash,
+        // which only differ in the decoder for contexts of more than 4 bytes
+        let config = CompressConfig {
+            model: ModelConfig::Mixer {
+                models: vec![ModelConfig::NOrderByte {
+                    byte_mask: "0b11110000".to_owned(),
+                    table: None,
+                    counter: CounterType::Probability,
+                }],
+            },
+            hash_table_size: HashTableSize::Pow2(16),
+            checked_hash_table: true,
+            ..Default::default()
+        };
+
+        let input = std::fs::read("tests/ray_tracer/index.js").unwrap();
+        node_round_trip(config, &input, "testout/round_trip_high_order_checked");
+    }
+
+    #[test]
+    pub fn round_trip_separate_hash_tables() {
+        let mut model_config = create_default_model_config();
+        let ModelConfig::Mixer { models } = &mut model_config else {
+            unreachable!()
+        };
+        for model in models.iter_mut() {
+            if let ModelConfig::NOrderByte {
+                byte_mask, table, ..
+            } = model
+            {
+                if byte_mask == "0b00000000" || byte_mask == "0b00000001" {
+                    *table = Some("low_orders".to_owned());
+                }
+            }
+        }
+
+        let config = CompressConfig {
+            model: model_config,
+            hash_tables: [(
+                "low_orders".to_owned(),
+                HashTableConfig {
+                    size: HashTableSize::Pow2(17),
+                    checked: true,
+                },
+            )]
+            .into(),
+            ..Default::default()
+        };
+
+        let input = std::fs::read("tests/ray_tracer/index.js").unwrap();
+        node_round_trip(config, &input, "testout/round_trip_separate_tables");
+    }
+
+    #[test]
+    pub fn round_trip_bit_history() {
+        let mut model_config = create_default_model_config();
+        let ModelConfig::Mixer { models } = &mut model_config else {
+            unreachable!()
+        };
+        for model in models.iter_mut() {
+            if let ModelConfig::NOrderByte { counter, .. } | ModelConfig::Word { counter, .. } =
+                model
+            {
+                *counter = CounterType::BitHistory;
+            }
+        }
+
+        let config = CompressConfig {
+            model: model_config,
+            checked_hash_table: true,
+            ..Default::default()
+        };
+
+        let input = std::fs::read("tests/ray_tracer/index.js").unwrap();
+        node_round_trip(config, &input, "testout/round_trip_bit_history");
+    }
+
+    #[test]
+    pub fn round_trip_indirect() {
+        let mut model_config = create_default_model_config();
+        let ModelConfig::Mixer { models } = &mut model_config else {
+            unreachable!()
+        };
+        models.push(ModelConfig::Indirect {
+            order: 1,
+            table: None,
+            counter: CounterType::Probability,
+        });
+        models.push(ModelConfig::Indirect {
+            order: 2,
+            table: None,
+            counter: CounterType::BitHistory,
+        });
+
+        let config = CompressConfig {
+            model: model_config,
+            ..Default::default()
+        };
+
+        // Interleaved 16 bit values followed by text
+        let mut input: Vec<u8> = (0..8192u32)
+            .flat_map(|i| ((i % 7) as u16 * 1000 + (i % 3) as u16).to_le_bytes())
+            .collect();
+        input.extend(std::fs::read("tests/ray_tracer/index.js").unwrap());
+        node_round_trip(config, &input, "testout/round_trip_indirect");
+    }
+
+    #[test]
+    pub fn round_trip_record() {
+        let mut model_config = create_default_model_config();
+        let ModelConfig::Mixer { models } = &mut model_config else {
+            unreachable!()
+        };
+        models.push(ModelConfig::Record {
+            length: None,
+            records: 2,
+            table: None,
+            counter: CounterType::Probability,
+            file_starts: Vec::new(),
+        });
+        models.push(ModelConfig::Record {
+            length: Some(6),
+            records: 1,
+            table: None,
+            counter: CounterType::BitHistory,
+            file_starts: Vec::new(),
+        });
+
+        // Records of 6 bytes, records of 10 bytes and text, in files of their own
+        // so the length is detected for each of them
+        let mut input: Vec<u8> = (0..4096u32)
+            .flat_map(|i| {
+                let [x0, x1] = ((i * 37) as u16).to_le_bytes();
+                [x0, x1, (i % 5) as u8, 0x80, (i / 16) as u8, 0xff]
+            })
+            .collect();
+        let second_start = input.len() as u32;
+        input.extend((0..2048u32).flat_map(|i| {
+            let [x0, x1, x2, x3] = (i * i).to_le_bytes();
+            [
+                x0,
+                x1,
+                x2,
+                x3,
+                0x3f,
+                (i % 3) as u8,
+                0,
+                0x40,
+                (i / 8) as u8,
+                0x11,
+            ]
+        }));
+        let text_start = input.len() as u32;
+        input.extend(std::fs::read("tests/ray_tracer/index.js").unwrap());
+        model_config
+            .resolve_files(&FileRanges::from([
+                ("records6.bin".to_owned(), 0..second_start),
+                ("records10.bin".to_owned(), second_start..text_start),
+                ("index.js".to_owned(), text_start..input.len() as u32),
+            ]))
+            .unwrap();
+
+        let config = CompressConfig {
+            model: model_config,
+            ..Default::default()
+        };
+        node_round_trip(config, &input, "testout/round_trip_record");
+    }
+
+    #[test]
+    pub fn round_trip_image() {
+        let mut model_config = create_default_model_config();
+        let ModelConfig::Mixer { models } = &mut model_config else {
+            unreachable!()
+        };
+        models.push(ModelConfig::InFile {
+            file: "image.raw".to_owned(),
+            model: Box::new(ModelConfig::Image {
+                width: 64,
+                channels: 3,
+                bytes_per_channel: 1,
+                table: None,
+                counter: CounterType::Probability,
+            }),
+            range: None,
+        });
+        models.push(ModelConfig::InFile {
+            file: "gray16.raw".to_owned(),
+            model: Box::new(ModelConfig::Image {
+                width: 32,
+                channels: 1,
+                bytes_per_channel: 2,
+                table: None,
+                counter: CounterType::BitHistory,
+            }),
+            range: None,
+        });
+
+        // Text followed by a 64x48 RGB image and a 32x32 image of one 16 bit channel
+        let mut input = std::fs::read("tests/ray_tracer/index.js").unwrap();
+        let image_start = input.len() as u32;
+        input.extend((0..48u32).flat_map(|y| {
+            (0..64u32).flat_map(move |x| [(x * 4) as u8, (x + y * 3) as u8, (x ^ y) as u8])
+        }));
+        let gray16_start = input.len() as u32;
+        input.extend(
+            (0..32u32).flat_map(|y| {
+                (0..32u32).flat_map(move |x| ((x * x + y * 700) as u16).to_le_bytes())
+            }),
+        );
+        model_config
+            .resolve_files(&FileRanges::from([
+                ("image.raw".to_owned(), image_start..gray16_start),
+                ("gray16.raw".to_owned(), gray16_start..input.len() as u32),
+            ]))
+            .unwrap();
+
+        let config = CompressConfig {
+            model: model_config,
+            ..Default::default()
+        };
+        node_round_trip(config, &input, "testout/round_trip_image");
+    }
+
+    #[test]
+    pub fn round_trip_audio() {
+        let mut model_config = create_default_model_config();
+        let ModelConfig::Mixer { models } = &mut model_config else {
+            unreachable!()
+        };
+        models.push(ModelConfig::InFile {
+            file: "sample.raw".to_owned(),
+            model: Box::new(ModelConfig::Audio {
+                bits_per_sample: 16,
+                channels: 1,
+                table: None,
+                counter: CounterType::Probability,
+            }),
+            range: None,
+        });
+        models.push(ModelConfig::InFile {
+            file: "stereo.raw".to_owned(),
+            model: Box::new(ModelConfig::Audio {
+                bits_per_sample: 8,
+                channels: 2,
+                table: None,
+                counter: CounterType::BitHistory,
+            }),
+            range: None,
+        });
+
+        // Text followed by a decaying 16 bit triangle wave and unsigned 8 bit stereo
+        // with a different wave per channel
+        let mut input = std::fs::read("tests/ray_tracer/index.js").unwrap();
+        let sample_start = input.len() as u32;
+        input.extend((0..8192i32).flat_map(|i| {
+            let phase = (i * 300) % 8000 - 4000;
+            let value = (phase.abs() - 2000) * 8 * (8192 - i) / 8192 + i % 3;
+            (value as i16).to_le_bytes()
+        }));
+        let stereo_start = input.len() as u32;
+        input.extend((0..4096i32).flat_map(|i| {
+            [
+                (128 + (i * 7 % 200 - 100) / 2) as u8,
+                (128 + ((i as f32 * 0.05).sin() * 90.) as i32) as u8,
+            ]
+        }));
+        model_config
+            .resolve_files(&FileRanges::from([
+                ("sample.raw".to_owned(), sample_start..stereo_start),
+                ("stereo.raw".to_owned(), stereo_start..input.len() as u32),
+            ]))
+            .unwrap();
+
+        let config = CompressConfig {
+            model: model_config,
+            ..Default::default()
+        };
+        node_round_trip(config, &input, "testout/round_trip_audio");
+    }
+
+    #[test]
+    pub fn round_trip_float32() {
+        let mut model_config = create_default_model_config();
+        let ModelConfig::Mixer { models } = &mut model_config else {
+            unreachable!()
+        };
+        models.push(ModelConfig::Float32 {
+            stride: 3,
+            table: None,
+            counter: CounterType::Probability,
+        });
+        models.push(ModelConfig::Float32 {
+            stride: 1,
+            table: None,
+            counter: CounterType::BitHistory,
+        });
+
+        let config = CompressConfig {
+            model: model_config,
+            ..Default::default()
+        };
+
+        // Positions of a spiral, including values that extrapolate to infinity and NaN
+        let mut input: Vec<u8> = (0..4096)
+            .flat_map(|i| {
+                let t = i as f32 * 0.01;
+                [t.sin() * 3., t * 0.5, t.cos() * 3.]
+            })
+            .chain([f32::MAX, -f32::MAX, f32::NAN, f32::INFINITY, 0., -0.])
+            .flat_map(f32::to_le_bytes)
+            .collect();
+        input.extend(std::fs::read("tests/ray_tracer/index.js").unwrap());
+        node_round_trip(config, &input, "testout/round_trip_float32");
+    }
+
+    #[test]
+    pub fn round_trip_column() {
+        let mut model_config = create_default_model_config();
+        let ModelConfig::Mixer { models } = &mut model_config else {
+            unreachable!()
+        };
+        models.push(ModelConfig::Column {
+            table: None,
+            counter: CounterType::Probability,
+        });
+        models.push(ModelConfig::Indentation {
+            table: None,
+            counter: CounterType::BitHistory,
+        });
+
+        let config = CompressConfig {
+            model: model_config,
+            ..Default::default()
+        };
+
+        let mut input = [
+            include_str!("js_source/hash_map.js"),
+            include_str!("js_source/norder_byte.js"),
+        ]
+        .concat();
+        // Lines longer than the column model keeps
+        for i in 0..40 {
+            input += &format!("{:>6}:", i).repeat(50 + i % 7);
+            input.push('\n');
+        }
+        node_round_trip(config, input.as_bytes(), "testout/round_trip_column");
+    }
+
+    #[test]
+    pub fn round_trip_js_lexer() {
+        let mut model_config = create_default_model_config();
+        let ModelConfig::Mixer { models } = &mut model_config else {
+            unreachable!()
+        };
+        models.push(ModelConfig::JsLexer {
+            order: 1,
+            table: None,
+            counter: CounterType::Probability,
+        });
+        models.push(ModelConfig::JsLexer {
+            order: 4,
+            table: None,
+            counter: CounterType::BitHistory,
+        });
+
+        let config = CompressConfig {
+            model: model_config,
+            ..Default::default()
+        };
+
+        let mut input = br#"// Comment with 'quotes' and `backticks`
+let a = `template ${b.map(x => `nested ${x / 2}`).join("\"")} end`; /* block */
+let r = /[/\]]+"'/g.test(a) ? a / 2 / 3 : 'it\'s';
+switch (r) { case 1: return 2 / r; default: return x1 in y }
+"#
+        .to_vec();
+        input.extend(std::fs::read("tests/ray_tracer/index.js").unwrap());
+        node_round_trip(config, &input, "testout/round_trip_js_lexer");
+    }
+
+    #[test]
+    pub fn round_trip_glsl_token() {
+        let mut model_config = create_default_model_config();
+        let ModelConfig::Mixer { models } = &mut model_config else {
+            unreachable!()
+        };
+        models.push(ModelConfig::GlslToken {
+            table: None,
+            counter: CounterType::Probability,
+        });
+        models.push(ModelConfig::GlslToken {
+            table: None,
+            counter: CounterType::BitHistory,
+        });
+
+        let config = CompressConfig {
+            model: model_config,
+            ..Default::default()
+        };
+
+        let mut input = b"#version 300 es\nprecision highp float;\nuniform vec3 u_pos;\nvoid main(){\n  vec4 c=texture(t,uv.xy*.5)+vec4(u_pos.zyx,1.);\n  if(c.x>=0.5&&c.y!=1.0){c.rgb*=2.0;}\n  outColor=c;\n}\n".to_vec();
+        input.extend(std::fs::read("tests/ray_tracer/index.js").unwrap());
+        node_round_trip(config, &input, "testout/round_trip_glsl_token");
+    }
+
+    #[test]
+    pub fn round_trip_number() {
+        let mut model_config = create_default_model_config();
+        let ModelConfig::Mixer { models } = &mut model_config else {
+            unreachable!()
+        };
+        models.push(ModelConfig::Number {
+            table: None,
+            counter: CounterType::Probability,
+        });
+        models.push(ModelConfig::Number {
+            table: None,
+            counter: CounterType::BitHistory,
+        });
+
+        let config = CompressConfig {
+            model: model_config,
+            ..Default::default()
+        };
+
+        let mut input = br#"[{"p":[0.5,-12.25,1e3,.75,3E-7,2.5e+10],"v3":vec3(1.,2.,3.)},"x":1..toString(),0x1f]"#
+            .to_vec();
+        input.extend(std::fs::read("tests/ray_tracer/index.js").unwrap());
+        node_round_trip(config, &input, "testout/round_trip_number");
+    }
+
+    #[test]
+    pub fn round_trip_gru() {
+        let mut model_config = create_default_model_config();
+        let ModelConfig::Mixer { models } = &mut model_config else {
+            unreachable!()
+        };
+        models.push(ModelConfig::Gru { cells: 32 });
+        models.push(ModelConfig::Gru { cells: 5 });
+
+        let config = CompressConfig {
+            model: model_config,
+            ..Default::default()
+        };
+
+        let input = std::fs::read("tests/ray_tracer/index.js").unwrap();
+        node_round_trip(config, &input, "testout/round_trip_gru");
+    }
+
+    #[test]
+    pub fn round_trip_prime_decompressor() {
+        let config = CompressConfig {
+            prime_decompressor: true,
+            ..Default::default()
+        };
+
+        let input = std::fs::read("tests/ray_tracer/index.js").unwrap();
+        node_round_trip(config, &input, "testout/round_trip_prime_decompressor");
+    }
+
+    #[test]
+    pub fn prime_source_defines_model_and_decompress() {
+        let config = CompressConfig::default();
+        let source = output_generator::prime_source(
+            &config.model,
+            &config.hash_table_layouts(1024).unwrap(),
+        )
+        .unwrap();
+
+        // The boot script and the node decompressor call them by name
+        let output_dir = Path::new("testout/prime_source");
+        std::fs::create_dir_all(output_dir).unwrap();
+        std::fs::write(
+            output_dir.join("check.js"),
+            source
+                + ";\nif (typeof model.pred != 'function' || typeof decompress != 'function') process.exit(1);\n",
+        )
+        .unwrap();
+        let status = Command::new("node")
+            .arg(output_dir.join("check.js"))
+            .status()
+            .expect("Failed to run node");
+        assert!(status.success(), "Primed source lacks model or decompress");
+    }
+
+    #[test]
+    pub fn round_trip_prime_with() {
+        let config = CompressConfig {
+            prime_decompressor: true,
+            prime_with: Some("example/webgl_dictionary.txt".to_owned()),
+            ..Default::default()
+        };
+
+        let input = std::fs::read("tests/ray_tracer/index.js").unwrap();
+        node_round_trip(config, &input, "testout/round_trip_prime_with");
+    }
+
+    #[test]
+    pub fn round_trip_word_replace() {
+        let config = CompressConfig {
+            word_replace: vec!["index.js".to_owned()],
+            ..Default::default()
+        };
+
+        let input = std::fs::read("tests/ray_tracer/index.js").unwrap();
+        node_round_trip(config, &input, "testout/round_trip_word_replace");
+    }
+
+    #[test]
+    pub fn round_trip_filters() {
+        let config = CompressConfig {
+            filters: BTreeMap::from([(
+                "samples.raw".to_owned(),
+                vec![Filter::Transpose(2), Filter::Delta(1)],
+            )]),
+            ..Default::default()
+        };
+
+        // Decaying 16 bit triangle wave with an odd length, so the transpose leaves a tail
+        let input: Vec<u8> = (0..8191i32)
+            .flat_map(|i| {
+                let value = ((i % 64 - 32).abs() * 1000 * (8192 - i) / 8192) as i16;
+                value.to_le_bytes()
+            })
+            .chain([42])
+            .collect();
+        node_round_trip(config, &input, "testout/round_trip_filters");
+    }
+
+    #[test]
+    pub fn rename_js_keeps_output() {
+        let source = "let g=2;function f(a,b){let c={a,b},{d=3}=c,e=1;({e}=c);return c.a*b+d+e+g}\
+            function h(e){return[1,2].map(x=>x*e+g).join()}\
+            for(let i=0;i<3;i++)console.log(f(i,2),h(i),typeof e);";
+        let config = CompressConfig::default();
+        let layouts = config.hash_table_layouts(source.len()).unwrap();
+        let create_model = || config.model.create_model(&create_hash_tables(&layouts));
+        let renamed = js_renamer::rename_locals(source, &create_model, usize::MAX).unwrap();
+
+        let run = |source: &str| {
+            Command::new("node")
+                .args(["-e", source])
+                .output()
+                .expect("Failed to run node")
+                .stdout
+        };
+        assert_ne!(renamed.source, source);
+        assert_eq!(run(&renamed.source), run(source));
+    }
+
+    #[test]
+    pub fn rename_js_with_in_file_models() {
+        let source = "function f(a,b){let c=a*b;return c+a}console.log(f(2,3));";
+        let mut model_config = create_default_model_config();
+        let ModelConfig::Mixer { models } = &mut model_config else {
+            unreachable!()
+        };
+        for file in ["index.js", "data.bin"] {
+            models.push(ModelConfig::InFile {
+                file: file.to_owned(),
+                model: Box::new(ModelConfig::JsLexer {
+                    order: 2,
+                    table: None,
+                    counter: CounterType::Probability,
+                }),
+                range: None,
+            });
+        }
+        let config = CompressConfig {
+            model: model_config,
+            ..Default::default()
+        };
+
+        let renamed = rename_js_main(
+            &config,
+            "index.js",
+            &["data.bin".to_owned()],
+            source.as_bytes(),
+            usize::MAX,
+        )
+        .unwrap();
+        assert!(renamed.bits_after <= renamed.bits_before);
+    }
+
+    #[test]
+    pub fn optimize_order_groups_similar_files() {
+        // Costs a byte for every neighbour of another kind
+        let kinds = [0, 1, 2, 1, 2];
+        let encoded_len = |order: &[usize]| {
+            Ok(order
+                .windows(2)
+                .filter(|pair| kinds[pair[0]] != kinds[pair[1]])
+                .count() as i64)
+        };
+
+        let (order, len) = optimize_order(kinds.len(), encoded_len).unwrap();
+        assert_eq!(order, [0, 4, 2, 3, 1]);
+        assert_eq!(len, 2);
+    }
+
+    #[test]
+    pub fn hash_tables_fit_in_browser_buffers() {
+        let layouts = |pow2_size, checked_hash_table| {
+            CompressConfig {
+                hash_table_size: HashTableSize::Pow2(pow2_size),
+                checked_hash_table,
+                ..Default::default()
+            }
+            .hash_table_layouts(1024)
+        };
+        assert!(layouts(29, false).is_ok());
+        assert!(layouts(29, true).is_err());
+        assert!(layouts(28, true).is_ok());
+        assert!(layouts(30, false).is_err());
+    }
+
+    #[test]
+    pub fn add_to_mixer() {
+        let gru = ModelConfig::Gru { cells: 4 };
+        let mut model_config = ModelConfig::AdaptiveProbabilityMap {
+            model: Box::new(create_default_model_config()),
+            stages: vec![ApmConfig::default()],
+        };
+        model_config.add_to_mixer(gru.clone());
+        let ModelConfig::AdaptiveProbabilityMap { model, .. } = &model_config else {
+            unreachable!()
+        };
+        let ModelConfig::Mixer { models } = model.as_ref() else {
+            unreachable!()
+        };
+        assert!(matches!(models.last(), Some(ModelConfig::Gru { cells: 4 })));
+
+        let mut model_config = gru.clone();
+        model_config.add_to_mixer(gru);
+        assert!(matches!(model_config, ModelConfig::Mixer { models } if models.len() == 2));
+    }
+
+    #[test]
+    pub fn place_files_stores_noise() {
+        let text = "let x = [1, 2, 3].map(v => v * 2);\n"
+            .repeat(20)
+            .into_bytes();
+        let mut seed = 1u32;
+        let noise: Vec<u8> = (0..2000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1664525).wrapping_add(1013904223);
+                (seed >> 24) as u8
+            })
+            .collect();
+        let mut inputs = vec![
+            ("index.js".to_owned(), text.clone()),
+            ("noise.bin".to_owned(), noise.clone()),
+            ("copy.js".to_owned(), text.clone()),
+        ];
+        let mut word_transforms = vec![None; 3];
+        let mut bundled_files: Vec<BundledFile> = ["noise.bin", "copy.js"]
+            .iter()
+            .map(|name| BundledFile {
+                path: PathBuf::from(name),
+                start_offset: 0,
+                length: 0,
+                decoded: None,
+            })
+            .collect();
+        bundled_files[1].length = text.len() as u32;
+        let config = CompressConfig::default();
+        let hash_table_layouts = config.hash_table_layouts(4096).unwrap();
+
+        let placed_files = place_files(
+            &config,
+            &hash_table_layouts,
+            &mut inputs,
+            &mut word_transforms,
+            &mut bundled_files,
+            None,
+        )
+        .unwrap();
+        assert_eq!(placed_files.len(), 1);
+        assert_eq!(placed_files[0].path, PathBuf::from("noise.bin"));
+        assert_eq!(placed_files[0].content, noise);
+        assert!(!placed_files[0].deflated);
+        assert_eq!(inputs.len(), 2);
+        assert_eq!(word_transforms.len(), 2);
+        assert_eq!(bundled_files.len(), 1);
+        assert_eq!(bundled_files[0].start_offset, text.len() as u32);
+    }
+
+    #[test]
+    pub fn same_file_names_are_rejected() {
+        let paths = ["src/index.js", "a/data.bin", "b/data.bin"].map(str::to_owned);
+        assert!(check_file_names(&paths[..2]).is_ok());
+        assert!(check_file_names(&paths).is_err());
+    }
+
+    #[test]
+    pub fn unresolved_in_file_is_an_error() {
+        let model_config = ModelConfig::InFile {
+            file: "data.bin".to_owned(),
+            model: Box::new(create_default_model_config()),
+            range: None,
+        };
+        let hash_tables = CompressConfig::default().hash_table_layouts(1024).unwrap();
+        let mut features_used = output_generator::ModelRef::None;
+        assert!(output_generator::generate_js_decompression_code(
+            &model_config,
+            &hash_tables,
+            &mut features_used
+        )
+        .is_err());
+    }
+
     #[test]
     pub fn round_trip_random_data() {
         use rand::rngs::StdRng;
@@ -307,7 +2005,10 @@ mod node_tests {
 
         let hash_table = HashTable::<NOrderByteData>::new(26);
         let model = model_config
-            .create_model(Rc::new(RefCell::new(hash_table)))
+            .create_model(&HashTables::from([(
+                None,
+                Rc::new(RefCell::new(hash_table)),
+            )]))
             .expect("Failed to create model from config");
 
         let mut rng = StdRng::seed_from_u64(1337);
@@ -324,6 +2025,17 @@ mod node_tests {
                 output_dir: Path::new("testout/round_trip_rand").to_owned(),
                 target: output_generator::Target::Node,
                 model_config: model_config,
+                hash_tables: HashTableLayouts::from([(
+                    None,
+                    HashTableLayout {
+                        pow2_size: 26,
+                        checked: false,
+                    },
+                )]),
+                prime_source: None,
+                dictionary: None,
+                word_transforms: vec![],
+                filters: vec![],
             },
             input_bytes.len(),
             encoded_data,
@@ -333,10 +2045,11 @@ mod node_tests {
         )
         .expect("Failed to render output");
 
-        Command::new("node")
+        let status = Command::new("node")
             .arg("testout/round_trip_rand/index.mjs")
             .status()
             .expect("Failed to run node decompressor");
+        assert!(status.success(), "Node decompressor failed: {}", status);
 
         let output_path = Path::new("testout/round_trip_rand/output.bin");
         let output_file = File::open(output_path).expect("Failed to open output.bin");
@@ -37,163 +37,1950 @@ impl NOrderByteData {
     fn set_prob(&mut self, new_prob: i32) {
         self.0 = (new_prob as u32 & U24_MAX) | (self.0 & 0xFF000000);
     }
+
+    /// Bit history state of slots used with a `StateMap`.
+    /// Unused slots (zero count) are in the initial state.
+    fn state(&self) -> u8 {
+        if self.count() == 0 {
+            0
+        } else {
+            (self.prob() >> 16) as u8
+        }
+    }
+
+    /// The state is stored in the high bits of the probability,
+    /// so models reading the slot as a probability on hash collisions
+    /// still get a usable value
+    fn set_state(&mut self, state: u8, count: u32) {
+        self.set_count(count.max(1));
+        self.set_prob(((state as i32) << 16) | 0x8000);
+    }
+}
+
+/// Nonstationary bit history states, as in ZPAQ.
+/// Each state represents a count of zeros (n0) and ones (n1),
+/// where the opposite count is discounted when a bit is observed,
+/// so the history favours recent bits.
+struct StateTable {
+    next: [[u8; 2]; 256],
+    counts: [[u8; 2]; 256],
+}
+
+impl StateTable {
+    const N: usize = 50;
+
+    /// Number of states representing the counts n0, n1 (0 to 2)
+    fn num_states(n0: i32, n1: i32) -> i32 {
+        const BOUND: [i32; 6] = [20, 48, 15, 8, 6, 5];
+        if n0 < n1 {
+            return Self::num_states(n1, n0);
+        }
+        if n0 < 0 || n1 < 0 || n1 >= BOUND.len() as i32 || n0 > BOUND[n1 as usize] {
+            return 0;
+        }
+        1 + (n1 > 0 && n0 + n1 <= 17) as i32
+    }
+
+    /// New value of the opposite count when a bit is observed
+    fn discount(n: i32) -> i32 {
+        [1, 2, 3, 4, 5, 7, 8].iter().filter(|&&x| n >= x).count() as i32
+    }
+
+    fn next_counts(n0: i32, n1: i32, bit: u8) -> (i32, i32) {
+        if n0 < n1 {
+            let (n1, n0) = Self::next_counts(n1, n0, 1 - bit);
+            return (n0, n1);
+        }
+
+        let (mut n0, mut n1) = if bit == 1 {
+            (Self::discount(n0), n1 + 1)
+        } else {
+            (n0 + 1, Self::discount(n1))
+        };
+
+        while Self::num_states(n0, n1) == 0 {
+            if n1 < 2 {
+                n0 -= 1;
+            } else {
+                n0 = (n0 * (n1 - 1) + (n1 / 2)) / n1;
+                n1 -= 1;
+            }
+        }
+
+        (n0, n1)
+    }
+
+    fn new() -> Self {
+        // Assign states by increasing total count,
+        // with two states for counts where the last bit matters
+        let mut states = vec![[[0u8; 2]; Self::N]; Self::N];
+        let mut state = 0;
+        for total in 0..Self::N as i32 {
+            for n1 in 0..=total {
+                let n0 = total - n1;
+                let n = Self::num_states(n0, n1);
+                if n > 0 {
+                    states[n0 as usize][n1 as usize] = [state as u8, (state + n - 1) as u8];
+                    state += n;
+                }
+            }
+        }
+
+        let mut table = Self {
+            next: [[0; 2]; 256],
+            counts: [[0; 2]; 256],
+        };
+        for n0 in 0..Self::N as i32 {
+            for n1 in 0..Self::N as i32 {
+                for last_bit in 0..Self::num_states(n0, n1) {
+                    let s = states[n0 as usize][n1 as usize][last_bit as usize] as usize;
+                    for bit in 0..2 {
+                        let (s0, s1) = Self::next_counts(n0, n1, bit);
+                        table.next[s][bit as usize] =
+                            states[s0 as usize][s1 as usize][bit as usize];
+                    }
+                    table.counts[s] = [n0 as u8, n1 as u8];
+                }
+            }
+        }
+
+        table
+    }
+
+    fn next(&self, state: u8, bit: u8) -> u8 {
+        self.next[state as usize][bit as usize]
+    }
+
+    /// Number of bits observed in the state
+    fn count(&self, state: u8) -> u32 {
+        let [n0, n1] = self.counts[state as usize];
+        n0 as u32 + n1 as u32
+    }
+}
+
+/// Maps bit history states to adaptive probabilities
+pub struct StateMap {
+    table: StateTable,
+    counters: [NOrderByteData; 256],
+    max_count: u32,
+}
+
+impl StateMap {
+    pub fn new(max_count: u32) -> Self {
+        let table = StateTable::new();
+        let counters = std::array::from_fn(|state| {
+            let [n0, n1] = table.counts[state];
+            let mut counter = NOrderByteData::default();
+            counter.set_prob(
+                ((n1 as u64 * 2 + 1) * U24_MAX as u64 / ((n0 as u64 + n1 as u64) * 2 + 2)) as i32,
+            );
+            counter
+        });
+
+        Self {
+            table,
+            counters,
+            max_count,
+        }
+    }
+
+    fn prob(&self, state: u8) -> i32 {
+        self.counters[state as usize].prob()
+    }
+
+    /// Updates the probability of the state and returns the next state
+    fn learn(&mut self, state: u8, bit: u8) -> u8 {
+        let counter = &mut self.counters[state as usize];
+        let (mut count, mut prob) = (counter.count(), counter.prob());
+        if count < self.max_count {
+            count += 1;
+        }
+
+        prob += (U24_MAX as f64
+            * ((bit as f64 - (prob as f64 / U24_MAX as f64)) / (count as f64 + 1.5)))
+            as i32;
+        counter.set_count(count);
+        counter.set_prob(prob);
+
+        self.table.next(state, bit)
+    }
+}
+
+/// Number of entries per bucket when collision checking is enabled
+const BUCKET_SIZE: usize = 4;
+
+pub trait HashRecord: Default + Clone {
+    /// Entries with the lowest priority are replaced first on collisions.
+    /// Entries with zero priority are considered unused.
+    fn priority(&self) -> u32;
+}
+
+impl HashRecord for NOrderByteData {
+    fn priority(&self) -> u32 {
+        self.count()
+    }
+}
+
+pub struct HashTable<Record> {
+    table: Vec<Record>,
+    pow2_size: u32,
+    hash_mask: usize,
+
+    // Check byte per entry, only used when collision checking is enabled
+    tags: Vec<u8>,
+    default_record: Record,
+
+    /// Number of entries inserted into a checked table
+    pub insertions: usize,
+    /// Number of used entries replaced by an unrelated context
+    pub collisions: usize,
+}
+
+fn hash(mut value: u32, shift: u32) -> u32 {
+    const K_MUL: u32 = 0x9E35A7BD;
+    value ^= value >> shift;
+    K_MUL.wrapping_mul(value) >> shift
+}
+
+impl<Record> HashTable<Record>
+where
+    Record: HashRecord,
+{
+    pub fn new(pow2_size: u32) -> Self {
+        Self::with_tags(pow2_size, false)
+    }
+
+    /// Creates a table where each key is verified against a check byte.
+    /// Keys are placed in buckets of `BUCKET_SIZE` entries, evicting the entry
+    /// with the lowest priority when a bucket is full.
+    pub fn new_checked(pow2_size: u32) -> Self {
+        Self::with_tags(pow2_size, true)
+    }
+
+    fn with_tags(pow2_size: u32, checked: bool) -> Self {
+        let context_size = (1 << pow2_size) as usize;
+        let tags_size = if checked { context_size } else { 0 };
+        println!(
+            "Hash table Size: {} MiB",
+            (size_of::<Record>() * context_size + tags_size) / (1024 * 1024)
+        );
+
+        Self {
+            table: vec![Record::default(); context_size],
+            pow2_size,
+            hash_mask: context_size - 1,
+            tags: vec![0; tags_size],
+            default_record: Record::default(),
+            insertions: 0,
+            collisions: 0,
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    pub fn is_checked(&self) -> bool {
+        !self.tags.is_empty()
+    }
+
+    /// Check byte from the key bits that don't select the bucket: the low 2 bits,
+    /// which select the entry within the bucket, and the bits above the index
+    /// folded into 6 bits. From 2^26 entries on, these are all the bits the keys
+    /// of a bucket differ in, so each of them gets its own tag.
+    fn tag(key: u32, pow2_size: u32) -> u8 {
+        let high = key >> pow2_size;
+        let folded = high ^ (high >> 6) ^ (high >> 12) ^ (high >> 18) ^ (high >> 24);
+        (((folded & 0x3f) << 2) | (key & 3)) as u8
+    }
+
+    fn bucket(&self, key: u32) -> std::ops::Range<usize> {
+        let start = key as usize & self.hash_mask & !(BUCKET_SIZE - 1);
+        start..start + BUCKET_SIZE
+    }
+
+    fn find(&self, key: u32) -> Option<usize> {
+        let tag = Self::tag(key, self.pow2_size);
+        self.bucket(key)
+            .find(|&idx| self.tags[idx] == tag && self.table[idx].priority() > 0)
+    }
+
+    pub fn get(&self, key: u32) -> &Record {
+        if !self.is_checked() {
+            return &self.table[key as usize & self.hash_mask];
+        }
+
+        match self.find(key) {
+            Some(idx) => &self.table[idx],
+            None => &self.default_record,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: u32) -> &mut Record {
+        if !self.is_checked() {
+            return &mut self.table[key as usize & self.hash_mask];
+        }
+
+        let idx = match self.find(key) {
+            Some(idx) => idx,
+            None => {
+                let idx = self
+                    .bucket(key)
+                    .min_by_key(|&idx| self.table[idx].priority())
+                    .unwrap();
+                self.insertions += 1;
+                if self.table[idx].priority() > 0 {
+                    self.collisions += 1;
+                }
+
+                self.table[idx] = self.default_record.clone();
+                self.tags[idx] = Self::tag(key, self.pow2_size);
+                idx
+            }
+        };
+
+        &mut self.table[idx]
+    }
+}
+
+/// Models predicting from hash table slots
+pub trait BitHistoryCounters: Model + Sized {
+    /// Stores bit history states in the slots, which are mapped to probabilities
+    /// through an adaptive state map. Reacts faster to changes in a context.
+    fn with_bit_history(self) -> Self;
+}
+
+/// Predicts bits from the slots of a hash table, keyed by a context hash
+/// computed by the owning model
+struct ContextSlots {
+    hash_table: Rc<RefCell<HashTable<NOrderByteData>>>,
+    max_count: u32,
+    // Slots store bit history states instead of probabilities when set
+    state_map: Option<StateMap>,
+}
+
+impl ContextSlots {
+    fn new(hash_table: Rc<RefCell<HashTable<NOrderByteData>>>) -> Self {
+        Self {
+            hash_table,
+            max_count: 15,
+            state_map: None,
+        }
+    }
+
+    fn use_bit_history(&mut self, magic_num: &mut u32) {
+        self.state_map = Some(StateMap::new(255));
+        // Don't share slots with probability slots of the same context
+        *magic_num = hash(magic_num.wrapping_add(1), 2);
+    }
+
+    fn pred(&self, key: u32) -> f64 {
+        let entry = self.hash_table.borrow().get(key).clone();
+
+        let prob = match &self.state_map {
+            Some(state_map) => state_map.prob(entry.state()),
+            None => entry.prob(),
+        };
+        prob_stretch(prob as f64 / U24_MAX as f64)
+    }
+
+    fn learn(&mut self, key: u32, bit: u8) {
+        let mut hash_table = self.hash_table.borrow_mut();
+        let inst = hash_table.get_mut(key);
+
+        if let Some(state_map) = &mut self.state_map {
+            let next_state = state_map.learn(inst.state(), bit);
+            inst.set_state(next_state, state_map.table.count(next_state));
+        } else {
+            let (mut count, mut prob) = (inst.count(), inst.prob());
+            if count < self.max_count {
+                count += 1;
+            }
+
+            let count_sqrt = count as f64 + 0.2;
+            // Learning function
+            prob += (U24_MAX as f64 * ((bit as f64 - (prob as f64 / U24_MAX as f64)) / count_sqrt))
+                as i32;
+
+            inst.set_count(count);
+            inst.set_prob(prob);
+        }
+    }
+}
+
+/// Slots of several contexts, whose predictions are mixed by a small
+/// mixer so a model can contribute all of them as a single prediction
+struct MixedContexts {
+    slots: ContextSlots,
+    ctxs: Vec<u32>,
+    weights: Vec<f64>,
+    last_p: Vec<f64>,
+    last_total_p: f64,
+}
+
+impl MixedContexts {
+    fn new(hash_table: Rc<RefCell<HashTable<NOrderByteData>>>, count: usize) -> Self {
+        Self {
+            slots: ContextSlots::new(hash_table),
+            ctxs: vec![0; count],
+            weights: vec![0.3; count],
+            last_p: vec![0.; count],
+            last_total_p: 0.,
+        }
+    }
+
+    fn pred(&mut self, bit_ctx: u32) -> f64 {
+        let mut sum = 0.;
+        for i in 0..self.ctxs.len() {
+            let p = self.slots.pred(self.ctxs[i] ^ bit_ctx);
+            self.last_p[i] = p;
+            sum += p * self.weights[i];
+        }
+
+        self.last_total_p = prob_squash(sum);
+        sum
+    }
+
+    fn learn(&mut self, bit_ctx: u32, bit: u8) {
+        const LEARNING_RATE: f64 = 0.002;
+        let pred_err = bit as f64 - self.last_total_p;
+        for i in 0..self.ctxs.len() {
+            self.slots.learn(self.ctxs[i] ^ bit_ctx, bit);
+            self.weights[i] += LEARNING_RATE * pred_err * self.last_p[i];
+        }
+    }
+
+    /// Hashes the context values, `values[i]` being the context of slot `i`.
+    /// `selector` separates the statistics of e.g. the bytes within a sample.
+    fn set_ctxs(&mut self, values: &[u32], selector: u32, magic_num: u32) {
+        for (i, &value) in values.iter().enumerate() {
+            // The hash of small numbers needs to be spread, or contexts collide a lot
+            self.ctxs[i] = (hash(value, 3)
+                .wrapping_add(hash(i as u32 | (selector << 8), 3).wrapping_mul(0x2545F491)))
+            .wrapping_add(1)
+            .wrapping_mul(magic_num);
+        }
+    }
+}
+
+/// Context hash of NOrderByte models, `magic_num` separates the models
+fn norder_hash(masked_prev_bytes: u64, magic_num: u32) -> u32 {
+    (hash((masked_prev_bytes >> 32) as u32, 3)
+        .wrapping_mul(9)
+        .wrapping_add(hash(masked_prev_bytes as u32, 3)))
+    .wrapping_add(1) // To ensure ctx doesn't overlap between models
+    .wrapping_mul(magic_num)
+}
+
+/// NOrderByte model for byte predictions
+/// Can describe [0, 8] order models and partial models
+/// It also supports being a word model
+/// (using characters as window filters)
+pub struct NOrderByte {
+    ctx: u32,
+    slots: ContextSlots,
+
+    magic_num: u32,
+    prev_bytes: u64,
+    mask: u64,
+    is_word_model: bool,
+
+    bit_ctx: u32,
+}
+
+impl NOrderByte {
+    pub fn new_norder_model(
+        byte_mask: u8,
+        hash_table: Rc<RefCell<HashTable<NOrderByteData>>>,
+        max_count: u32,
+    ) -> Self {
+        assert!(max_count <= 255);
+
+        let mut bit_mask: u64 = 0;
+        for i in 0..8 {
+            bit_mask |= ((byte_mask >> i) & 1) as u64 * (0xff << (i * 8));
+        }
+
+        Self {
+            ctx: 0,
+            bit_ctx: 1,
+            magic_num: hash(byte_mask as u32, 2),
+            slots: ContextSlots::new(hash_table),
+            prev_bytes: 0,
+            mask: bit_mask,
+            is_word_model: false,
+        }
+    }
+
+    pub fn new_word_model(
+        hash_table: Rc<RefCell<HashTable<NOrderByteData>>>,
+        _max_count: u32,
+    ) -> Self {
+        Self {
+            ctx: 0,
+            bit_ctx: 1,
+            magic_num: hash(1337 as u32, 2),
+            slots: ContextSlots::new(hash_table),
+            prev_bytes: 2166136261,
+            mask: u64::MAX,
+            is_word_model: true,
+        }
+    }
+}
+
+impl BitHistoryCounters for NOrderByte {
+    fn with_bit_history(mut self) -> Self {
+        self.slots.use_bit_history(&mut self.magic_num);
+        self
+    }
+}
+
+impl Model for NOrderByte {
+    fn pred(&mut self) -> f64 {
+        self.slots.pred(self.ctx ^ self.bit_ctx)
+    }
+
+    fn learn(&mut self, bit: u8) {
+        self.slots.learn(self.ctx ^ self.bit_ctx, bit);
+
+        self.bit_ctx = (self.bit_ctx << 1) | bit as u32;
+        if self.bit_ctx >= 256 {
+            let current_byte = self.bit_ctx & 0xff;
+
+            if self.is_word_model {
+                let next_char = current_byte as u8 as char;
+                if next_char.is_ascii_alphanumeric() {
+                    self.prev_bytes = self.prev_bytes ^ next_char.to_ascii_lowercase() as u64;
+                    self.prev_bytes = self.prev_bytes.wrapping_mul(16777619) >> 16;
+                } else {
+                    self.prev_bytes = 2166136261;
+                }
+            } else {
+                self.prev_bytes = (self.prev_bytes << 8) | current_byte as u64;
+            }
+
+            self.ctx = norder_hash(self.prev_bytes & self.mask, self.magic_num);
+
+            // Reset bit_ctx
+            self.bit_ctx = 1;
+        }
+    }
+}
+
+/// Indirect context model
+/// Maps an order-1 or order-2 context to the bytes that followed it the last
+/// two times, and predicts from that byte history instead of the context.
+/// Catches alternating values, e.g. in interleaved binary data.
+pub struct Indirect {
+    ctx: u32,
+    slots: ContextSlots,
+
+    magic_num: u32,
+    prev_bytes: u32,
+    order_mask: u32,
+    /// Byte history by order-n context
+    histories: Vec<u16>,
+
+    bit_ctx: u32,
+}
+
+impl Indirect {
+    pub fn new(order: u32, hash_table: Rc<RefCell<HashTable<NOrderByteData>>>) -> Self {
+        assert!((1..=2).contains(&order));
+
+        let order_mask = (1u32 << (order * 8)) - 1;
+        Self {
+            ctx: 0,
+            bit_ctx: 1,
+            magic_num: hash(0x1d00 | order, 2),
+            slots: ContextSlots::new(hash_table),
+            prev_bytes: 0,
+            order_mask,
+            histories: vec![0; order_mask as usize + 1],
+        }
+    }
+}
+
+impl BitHistoryCounters for Indirect {
+    fn with_bit_history(mut self) -> Self {
+        self.slots.use_bit_history(&mut self.magic_num);
+        self
+    }
+}
+
+impl Model for Indirect {
+    fn pred(&mut self) -> f64 {
+        self.slots.pred(self.ctx ^ self.bit_ctx)
+    }
+
+    fn learn(&mut self, bit: u8) {
+        self.slots.learn(self.ctx ^ self.bit_ctx, bit);
+
+        self.bit_ctx = (self.bit_ctx << 1) | bit as u32;
+        if self.bit_ctx >= 256 {
+            let current_byte = self.bit_ctx & 0xff;
+
+            // Append the byte to the history of the context it followed
+            let history = &mut self.histories[(self.prev_bytes & self.order_mask) as usize];
+            *history = (*history << 8) | current_byte as u16;
+
+            self.prev_bytes = (self.prev_bytes << 8) | current_byte;
+            let history = self.histories[(self.prev_bytes & self.order_mask) as usize];
+            self.ctx = hash(history as u32, 3)
+                .wrapping_add(1)
+                .wrapping_mul(self.magic_num);
+
+            self.bit_ctx = 1;
+        }
+    }
+}
+
+/// Longest record length the record model can detect or use
+pub const MAX_RECORD_LENGTH: u32 = 4096;
+/// Repetitions of a distance needed before it's used as the record length
+const RECORD_MIN_VOTES: u32 = 8;
+
+/// Record model for fixed-width binary data
+/// Uses the bytes at the same column of the previous one or two records
+/// and the column as context. The record length is either fixed or detected
+/// from distances that repeat between occurrences of the same byte,
+/// starting over with every file.
+pub struct Record {
+    ctx: u32,
+    slots: ContextSlots,
+
+    magic_num: u32,
+    records: u32,
+    length: u32,
+    is_auto: bool,
+
+    /// Previous bytes, enough to look back two records
+    history: Vec<u8>,
+    pos: u32,
+    /// Last three positions of every byte value
+    byte_positions: [[u32; 3]; 256],
+    candidate_length: u32,
+    votes: u32,
+    /// Sorted start positions of the files, detection starts over at each of them
+    file_starts: Vec<u32>,
+    /// Index of the next file start in `file_starts`
+    next_file: usize,
+
+    bit_ctx: u32,
+}
+
+impl Record {
+    /// `length` of zero detects the record length from the data of each file
+    /// starting at `file_starts`.
+    /// `records` is the number of previous records used as context, 1 or 2.
+    pub fn new(
+        length: u32,
+        records: u32,
+        file_starts: Vec<u32>,
+        hash_table: Rc<RefCell<HashTable<NOrderByteData>>>,
+    ) -> Self {
+        assert!(length <= MAX_RECORD_LENGTH);
+        assert!((1..=2).contains(&records));
+
+        Self {
+            ctx: 0,
+            bit_ctx: 1,
+            magic_num: hash(0x2e00 | records, 2),
+            slots: ContextSlots::new(hash_table),
+            records,
+            length,
+            is_auto: length == 0,
+            history: vec![0; 2 * MAX_RECORD_LENGTH as usize],
+            pos: 0,
+            byte_positions: [[0; 3]; 256],
+            candidate_length: 0,
+            votes: 0,
+            file_starts,
+            next_file: 0,
+        }
+    }
+
+    /// Forgets the detected length when a new file starts at `pos`
+    fn start_file(&mut self) {
+        let mut is_file_start = false;
+        while let Some(&start) = self.file_starts.get(self.next_file) {
+            if start > self.pos {
+                break;
+            }
+            is_file_start |= start == self.pos;
+            self.next_file += 1;
+        }
+
+        if is_file_start {
+            self.byte_positions = [[0; 3]; 256];
+            self.candidate_length = 0;
+            self.votes = 0;
+            self.length = 0;
+        }
+    }
+
+    fn history_at(&self, pos: u32) -> u8 {
+        self.history[pos as usize & (self.history.len() - 1)]
+    }
+
+    fn detect_length(&mut self, byte: u8) {
+        let [p1, p2, p3] = self.byte_positions[byte as usize];
+        let distance = self.pos.wrapping_sub(p1);
+        if (2..=MAX_RECORD_LENGTH).contains(&distance)
+            && distance == p1.wrapping_sub(p2)
+            && distance == p2.wrapping_sub(p3)
+        {
+            // Majority vote, so a few unrelated repetitions don't switch the length
+            if distance == self.candidate_length {
+                self.votes = (self.votes + 1).min(2 * RECORD_MIN_VOTES);
+            } else if self.votes == 0 {
+                self.candidate_length = distance;
+                self.votes = 1;
+            } else {
+                self.votes -= 1;
+            }
+
+            if self.votes >= RECORD_MIN_VOTES {
+                self.length = self.candidate_length;
+            }
+        }
+        self.byte_positions[byte as usize] = [self.pos, p1, p2];
+    }
+
+    fn update_ctx(&mut self) {
+        let (mut column, mut above) = (0, 0);
+        if self.length > 0 {
+            column = self.pos % self.length;
+            above = self.history_at(self.pos.wrapping_sub(self.length)) as u32;
+            if self.records == 2 {
+                above |= (self.history_at(self.pos.wrapping_sub(2 * self.length)) as u32) << 8;
+            }
+        }
+
+        self.ctx = (hash(column | (self.length << 13), 3)
+            .wrapping_mul(9)
+            .wrapping_add(hash(above, 3)))
+        .wrapping_add(1)
+        .wrapping_mul(self.magic_num);
+    }
+}
+
+impl BitHistoryCounters for Record {
+    fn with_bit_history(mut self) -> Self {
+        self.slots.use_bit_history(&mut self.magic_num);
+        self
+    }
+}
+
+impl Model for Record {
+    fn pred(&mut self) -> f64 {
+        self.slots.pred(self.ctx ^ self.bit_ctx)
+    }
+
+    fn learn(&mut self, bit: u8) {
+        self.slots.learn(self.ctx ^ self.bit_ctx, bit);
+
+        self.bit_ctx = (self.bit_ctx << 1) | bit as u32;
+        if self.bit_ctx >= 256 {
+            let current_byte = (self.bit_ctx & 0xff) as u8;
+
+            if self.is_auto {
+                self.detect_length(current_byte);
+            }
+            let history_mask = self.history.len() - 1;
+            self.history[self.pos as usize & history_mask] = current_byte;
+            self.pos = self.pos.wrapping_add(1);
+            if self.is_auto {
+                self.start_file();
+            }
+            self.update_ctx();
+
+            self.bit_ctx = 1;
+        }
+    }
+}
+
+/// 2D image model for raw pixel data
+/// Predicts from the west, north, north-west and north-east neighbours
+/// of the same channel and simple gradients of them.
+pub struct Image {
+    contexts: MixedContexts,
+
+    magic_num: u32,
+    pixel_size: u32,
+    row_size: u32,
+
+    /// Previous bytes, enough to look back two rows
+    history: Vec<u8>,
+    pos: u32,
+
+    bit_ctx: u32,
+}
+
+impl Image {
+    pub fn new(
+        width: u32,
+        channels: u32,
+        bytes_per_channel: u32,
+        hash_table: Rc<RefCell<HashTable<NOrderByteData>>>,
+    ) -> Self {
+        let pixel_size = channels * bytes_per_channel;
+        let row_size = width * pixel_size;
+
+        Self {
+            contexts: MixedContexts::new(hash_table, 7),
+            magic_num: hash(0x1a00, 2),
+            pixel_size,
+            row_size,
+            history: vec![0; (2 * row_size + 4 * pixel_size).next_power_of_two() as usize],
+            pos: 0,
+            bit_ctx: 1,
+        }
+    }
+
+    fn update_ctxs(&mut self) {
+        let history_mask = self.history.len() - 1;
+        let at =
+            |back: u32| self.history[self.pos.wrapping_sub(back) as usize & history_mask] as i32;
+        let (px, row) = (self.pixel_size, self.row_size);
+        let (w, n, nw, ne) = (at(px), at(row), at(row + px), at(row - px));
+        let (ww, nn) = (at(2 * px), at(2 * row));
+
+        let values = [
+            w | (n << 8),
+            n | (nw << 8) | (ne << 16),
+            w | (ww << 8),
+            n | (nn << 8),
+            (w + n - nw).clamp(0, 255),
+            (w + ne - n).clamp(0, 255),
+            (w + n + 1) >> 1,
+        ]
+        .map(|value| value as u32);
+        // Byte within the pixel, so channels don't share statistics
+        self.contexts
+            .set_ctxs(&values, self.pos % self.pixel_size, self.magic_num);
+    }
+}
+
+impl BitHistoryCounters for Image {
+    fn with_bit_history(mut self) -> Self {
+        self.contexts.slots.use_bit_history(&mut self.magic_num);
+        self
+    }
+}
+
+impl Model for Image {
+    fn pred(&mut self) -> f64 {
+        self.contexts.pred(self.bit_ctx)
+    }
+
+    fn learn(&mut self, bit: u8) {
+        self.contexts.learn(self.bit_ctx, bit);
+
+        self.bit_ctx = (self.bit_ctx << 1) | bit as u32;
+        if self.bit_ctx >= 256 {
+            let history_mask = self.history.len() - 1;
+            self.history[self.pos as usize & history_mask] = self.bit_ctx as u8;
+            self.pos = self.pos.wrapping_add(1);
+            self.update_ctxs();
+
+            self.bit_ctx = 1;
+        }
+    }
+}
+
+/// Number of linear predictors of the audio model
+const AUDIO_PREDICTORS: usize = 3;
+
+/// Audio model for PCM data, 8 bit unsigned or 16 bit signed little-endian
+/// samples with interleaved channels.
+/// Predicts the bytes of a sample from linear predictions of the previous
+/// samples of the same channel, bucketed by the last error of each predictor,
+/// so it follows the shape of the waveform instead of exact byte repeats.
+pub struct Audio {
+    contexts: MixedContexts,
+
+    magic_num: u32,
+    bytes_per_sample: u32,
+    channels: u32,
+    pos: u32,
+    /// Bytes of the current sample seen so far
+    sample: u32,
+    /// Last three samples of every channel
+    samples: Vec<[i32; 3]>,
+    /// Last error of every predictor, for every channel
+    errors: Vec<[i32; AUDIO_PREDICTORS]>,
+    /// Predictions of the current sample
+    predictions: [i32; AUDIO_PREDICTORS],
+
+    bit_ctx: u32,
+}
+
+impl Audio {
+    pub fn new(
+        bits_per_sample: u32,
+        channels: u32,
+        hash_table: Rc<RefCell<HashTable<NOrderByteData>>>,
+    ) -> Self {
+        assert!(bits_per_sample == 8 || bits_per_sample == 16);
+
+        Self {
+            contexts: MixedContexts::new(hash_table, AUDIO_PREDICTORS + 2),
+            magic_num: hash(0xa000 | bits_per_sample, 2),
+            bytes_per_sample: bits_per_sample / 8,
+            channels,
+            pos: 0,
+            sample: 0,
+            samples: vec![[0; 3]; channels as usize],
+            errors: vec![[0; AUDIO_PREDICTORS]; channels as usize],
+            predictions: [0; AUDIO_PREDICTORS],
+            bit_ctx: 1,
+        }
+    }
+
+    fn channel(&self) -> usize {
+        ((self.pos / self.bytes_per_sample) % self.channels) as usize
+    }
+
+    /// Sign and magnitude of an error in a few buckets
+    fn error_bucket(error: i32) -> u32 {
+        (32 - error.unsigned_abs().leading_zeros()) * 2 + (error < 0) as u32
+    }
+
+    fn end_sample(&mut self) {
+        let value = if self.bytes_per_sample == 1 {
+            self.sample as i32 - 128
+        } else {
+            self.sample as u16 as i16 as i32
+        };
+
+        let channel = self.channel();
+        for (error, prediction) in self.errors[channel].iter_mut().zip(self.predictions) {
+            *error = value - prediction;
+        }
+        let [s1, s2, _] = self.samples[channel];
+        self.samples[channel] = [value, s1, s2];
+        self.sample = 0;
+    }
+
+    fn update_ctxs(&mut self) {
+        let byte_idx = self.pos % self.bytes_per_sample;
+        let channel = self.channel();
+        let [s1, s2, s3] = self.samples[channel];
+        let errors = self.errors[channel];
+
+        if byte_idx == 0 {
+            let max = (128 << (8 * (self.bytes_per_sample - 1))) - 1;
+            self.predictions =
+                [s1, 2 * s1 - s2, 3 * s1 - 3 * s2 + s3].map(|p| p.clamp(-max - 1, max));
+        }
+
+        // The byte each prediction expects, given the lower bytes of the sample
+        let expected_byte = |prediction: i32| -> i32 {
+            if self.bytes_per_sample == 1 {
+                prediction + 128
+            } else if byte_idx == 0 {
+                prediction & 0xff
+            } else {
+                ((prediction - self.sample as i32 + 128) >> 8) & 0xff
+            }
+        };
+
+        let mut values = [0u32; AUDIO_PREDICTORS + 2];
+        for (i, prediction) in self.predictions.into_iter().enumerate() {
+            values[i] = expected_byte(prediction) as u32 | (Self::error_bucket(errors[i]) << 8);
+        }
+        values[AUDIO_PREDICTORS] =
+            Self::error_bucket(errors[0]) | (Self::error_bucket(errors[1]) << 8);
+        values[AUDIO_PREDICTORS + 1] = expected_byte(s1) as u32 | (self.sample << 8);
+
+        self.contexts.set_ctxs(&values, byte_idx, self.magic_num);
+    }
+}
+
+impl BitHistoryCounters for Audio {
+    fn with_bit_history(mut self) -> Self {
+        self.contexts.slots.use_bit_history(&mut self.magic_num);
+        self
+    }
+}
+
+impl Model for Audio {
+    fn pred(&mut self) -> f64 {
+        self.contexts.pred(self.bit_ctx)
+    }
+
+    fn learn(&mut self, bit: u8) {
+        self.contexts.learn(self.bit_ctx, bit);
+
+        self.bit_ctx = (self.bit_ctx << 1) | bit as u32;
+        if self.bit_ctx >= 256 {
+            let byte_idx = self.pos % self.bytes_per_sample;
+            self.sample |= (self.bit_ctx & 0xff) << (8 * byte_idx);
+            if byte_idx == self.bytes_per_sample - 1 {
+                self.end_sample();
+            }
+            self.pos = self.pos.wrapping_add(1);
+            self.update_ctxs();
+
+            self.bit_ctx = 1;
+        }
+    }
+}
+
+/// Longest line prefix the column model keeps, the column context saturates there
+const MAX_COLUMN: usize = 256;
+
+/// Text model for line structure
+/// Either uses the character directly above in the previous line and the column
+/// as context, or the indentation depth and the first character of the line.
+pub struct Column {
+    ctx: u32,
+    slots: ContextSlots,
+
+    magic_num: u32,
+    is_indentation_model: bool,
+    line: Vec<u8>,
+    prev_line: Vec<u8>,
+    /// Number of leading spaces and tabs of the current line
+    indentation: u32,
+    /// First character of the current line after the indentation, 0 if not seen yet
+    first_char: u8,
+
+    bit_ctx: u32,
+}
+
+impl Column {
+    pub fn new_above_model(hash_table: Rc<RefCell<HashTable<NOrderByteData>>>) -> Self {
+        Self::new(false, hash_table)
+    }
+
+    pub fn new_indentation_model(hash_table: Rc<RefCell<HashTable<NOrderByteData>>>) -> Self {
+        Self::new(true, hash_table)
+    }
+
+    fn new(is_indentation_model: bool, hash_table: Rc<RefCell<HashTable<NOrderByteData>>>) -> Self {
+        Self {
+            ctx: 0,
+            bit_ctx: 1,
+            magic_num: hash(0xc010 | is_indentation_model as u32, 2),
+            slots: ContextSlots::new(hash_table),
+            is_indentation_model,
+            line: Vec::with_capacity(MAX_COLUMN),
+            prev_line: Vec::with_capacity(MAX_COLUMN),
+            indentation: 0,
+            first_char: 0,
+        }
+    }
+
+    fn update_line(&mut self, byte: u8) {
+        if byte == b'\n' {
+            std::mem::swap(&mut self.prev_line, &mut self.line);
+            self.line.clear();
+            self.indentation = 0;
+            self.first_char = 0;
+            return;
+        }
+
+        if self.first_char == 0 {
+            if byte == b' ' || byte == b'\t' {
+                self.indentation += 1;
+            } else {
+                self.first_char = byte;
+            }
+        }
+        if self.line.len() < MAX_COLUMN {
+            self.line.push(byte);
+        }
+    }
+}
+
+impl BitHistoryCounters for Column {
+    fn with_bit_history(mut self) -> Self {
+        self.slots.use_bit_history(&mut self.magic_num);
+        self
+    }
+}
+
+impl Model for Column {
+    fn pred(&mut self) -> f64 {
+        self.slots.pred(self.ctx ^ self.bit_ctx)
+    }
+
+    fn learn(&mut self, bit: u8) {
+        self.slots.learn(self.ctx ^ self.bit_ctx, bit);
+
+        self.bit_ctx = (self.bit_ctx << 1) | bit as u32;
+        if self.bit_ctx >= 256 {
+            let current_byte = self.bit_ctx as u8;
+            self.update_line(current_byte);
+
+            let column = self.line.len();
+            let (a, b) = if self.is_indentation_model {
+                (
+                    self.indentation.min(255) | ((self.first_char as u32) << 8),
+                    current_byte as u32,
+                )
+            } else {
+                let above = self.prev_line.get(column).copied().unwrap_or(0);
+                (above as u32 | ((column.min(255) as u32) << 8), 0)
+            };
+            self.ctx = (hash(a, 3).wrapping_mul(9).wrapping_add(hash(b, 3)))
+                .wrapping_add(1)
+                .wrapping_mul(self.magic_num);
+
+            self.bit_ctx = 1;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LexerState {
+    Code,
+    SingleQuote,
+    DoubleQuote,
+    Template,
+    Regexp,
+    RegexpClass,
+    LineComment,
+    BlockComment,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TokenClass {
+    None,
+    Word,
+    Number,
+    Operator,
+    /// Closing parenthesis or bracket
+    Close,
+    /// String or regexp literal
+    Literal,
+}
+
+/// Lightweight JavaScript lexer, only tracking what's useful as context.
+/// Mistakes only cost compression, as long as the JS version does the same.
+struct JsLexer {
+    state: LexerState,
+    /// Bracket nesting depth
+    depth: u32,
+    /// Class of the last token in code
+    token_class: TokenClass,
+    /// Whitespace followed the last token, so a word or number starts a new one
+    token_ended: bool,
+    /// Nesting depths of the `${` substitutions of enclosing template literals
+    template_depths: Vec<u32>,
+    escaped: bool,
+    /// A regexp was started, but no character of it seen yet
+    regexp_start: bool,
+    /// The last character was a division operator
+    division: bool,
+    prev_byte: u8,
+}
+
+impl JsLexer {
+    fn new() -> Self {
+        Self {
+            state: LexerState::Code,
+            depth: 0,
+            token_class: TokenClass::None,
+            token_ended: false,
+            template_depths: Vec::new(),
+            escaped: false,
+            regexp_start: false,
+            division: false,
+            prev_byte: 0,
+        }
+    }
+
+    fn update(&mut self, byte: u8) {
+        let prev_byte = std::mem::replace(&mut self.prev_byte, byte);
+
+        // Escaped characters in literals are skipped
+        if self.escaped {
+            self.escaped = false;
+            return;
+        }
+        if byte == b'\\'
+            && !matches!(
+                self.state,
+                LexerState::Code | LexerState::LineComment | LexerState::BlockComment
+            )
+        {
+            self.escaped = true;
+            return;
+        }
+
+        match self.state {
+            LexerState::Code => {
+                if std::mem::take(&mut self.division) && (byte == b'/' || byte == b'*') {
+                    self.state = if byte == b'/' {
+                        LexerState::LineComment
+                    } else {
+                        LexerState::BlockComment
+                    };
+                    return;
+                }
+
+                match byte {
+                    b'\'' => self.state = LexerState::SingleQuote,
+                    b'"' => self.state = LexerState::DoubleQuote,
+                    b'`' => self.state = LexerState::Template,
+                    b'/' => {
+                        // A regexp can't follow an operand
+                        if matches!(self.token_class, TokenClass::None | TokenClass::Operator) {
+                            self.state = LexerState::Regexp;
+                            self.regexp_start = true;
+                        } else {
+                            self.token_class = TokenClass::Operator;
+                            self.division = true;
+                        }
+                    }
+                    b'(' | b'[' | b'{' => {
+                        self.depth += 1;
+                        self.token_class = TokenClass::Operator;
+                    }
+                    b')' | b']' | b'}' => {
+                        self.depth = self.depth.saturating_sub(1);
+                        self.token_class = TokenClass::Close;
+                        if byte == b'}' && self.template_depths.last() == Some(&self.depth) {
+                            self.template_depths.pop();
+                            self.state = LexerState::Template;
+                        }
+                    }
+                    b' ' | b'\t' | b'\r' | b'\n' => self.token_ended = true,
+                    b'0'..=b'9' => {
+                        if self.token_class != TokenClass::Word || self.token_ended {
+                            self.token_class = TokenClass::Number;
+                        }
+                        self.token_ended = false;
+                    }
+                    b'a'..=b'z' | b'A'..=b'Z' | b'_' | b'$' | 0x80.. => {
+                        if self.token_class != TokenClass::Number || self.token_ended {
+                            self.token_class = TokenClass::Word;
+                        }
+                        self.token_ended = false;
+                    }
+                    _ => self.token_class = TokenClass::Operator,
+                }
+            }
+            LexerState::SingleQuote | LexerState::DoubleQuote => {
+                let quote = if self.state == LexerState::SingleQuote {
+                    b'\''
+                } else {
+                    b'"'
+                };
+                if byte == quote || byte == b'\n' {
+                    self.state = LexerState::Code;
+                    self.token_class = TokenClass::Literal;
+                }
+            }
+            LexerState::Template => {
+                if byte == b'`' {
+                    self.state = LexerState::Code;
+                    self.token_class = TokenClass::Literal;
+                } else if byte == b'{' && prev_byte == b'$' {
+                    self.template_depths.push(self.depth);
+                    self.depth += 1;
+                    self.state = LexerState::Code;
+                    self.token_class = TokenClass::Operator;
+                }
+            }
+            LexerState::Regexp => {
+                if std::mem::take(&mut self.regexp_start) && (byte == b'/' || byte == b'*') {
+                    self.state = if byte == b'/' {
+                        LexerState::LineComment
+                    } else {
+                        LexerState::BlockComment
+                    };
+                } else if byte == b'[' {
+                    self.state = LexerState::RegexpClass;
+                } else if byte == b'/' || byte == b'\n' {
+                    self.state = LexerState::Code;
+                    self.token_class = TokenClass::Literal;
+                }
+            }
+            LexerState::RegexpClass => {
+                if byte == b']' {
+                    self.state = LexerState::Regexp;
+                }
+            }
+            LexerState::LineComment => {
+                if byte == b'\n' {
+                    self.state = LexerState::Code;
+                }
+            }
+            LexerState::BlockComment => {
+                if byte == b'/' && prev_byte == b'*' {
+                    self.state = LexerState::Code;
+                }
+            }
+        }
+    }
+
+    fn ctx(&self) -> u32 {
+        self.state as u32 | (self.depth.min(15) << 3) | ((self.token_class as u32) << 7)
+    }
+}
+
+/// Mixes the state of a JavaScript lexer (literal or comment, bracket depth and
+/// previous token class) with the last bytes, so identifiers, strings and
+/// comments get separate statistics
+pub struct JsLexerModel {
+    ctx: u32,
+    slots: ContextSlots,
+
+    magic_num: u32,
+    lexer: JsLexer,
+    prev_bytes: u32,
+    mask: u32,
+
+    bit_ctx: u32,
+}
+
+impl JsLexerModel {
+    /// `order` is the number of last bytes in the context [0, 4]
+    pub fn new(order: u32, hash_table: Rc<RefCell<HashTable<NOrderByteData>>>) -> Self {
+        assert!(order <= 4);
+
+        Self {
+            ctx: 0,
+            bit_ctx: 1,
+            magic_num: hash(0x1e40 | order, 2),
+            slots: ContextSlots::new(hash_table),
+            lexer: JsLexer::new(),
+            prev_bytes: 0,
+            mask: ((1u64 << (8 * order)) - 1) as u32,
+        }
+    }
+}
+
+impl BitHistoryCounters for JsLexerModel {
+    fn with_bit_history(mut self) -> Self {
+        self.slots.use_bit_history(&mut self.magic_num);
+        self
+    }
+}
+
+impl Model for JsLexerModel {
+    fn pred(&mut self) -> f64 {
+        self.slots.pred(self.ctx ^ self.bit_ctx)
+    }
+
+    fn learn(&mut self, bit: u8) {
+        self.slots.learn(self.ctx ^ self.bit_ctx, bit);
+
+        self.bit_ctx = (self.bit_ctx << 1) | bit as u32;
+        if self.bit_ctx >= 256 {
+            let current_byte = self.bit_ctx as u8;
+            self.lexer.update(current_byte);
+            self.prev_bytes = (self.prev_bytes << 8) | current_byte as u32;
+
+            self.ctx = norder_hash(
+                ((self.lexer.ctx() as u64) << 32) | (self.prev_bytes & self.mask) as u64,
+                self.magic_num,
+            );
+
+            self.bit_ctx = 1;
+        }
+    }
+}
+
+/// FNV-1a offset basis, the hash of an empty word or token
+const FNV_BASIS: u32 = 2166136261;
+
+/// GLSL token model, a word model with a GLSL tokenizer
+/// Uses the hashes of the previous two tokens (keywords, identifiers and
+/// swizzles, numbers, operators and punctuation) and the current token as context.
+pub struct GlslToken {
+    ctx: u32,
+    slots: ContextSlots,
+
+    magic_num: u32,
+    /// Hash of the current token so far
+    token: u32,
+    token_class: u8,
+    prev_tokens: [u32; 2],
+
+    bit_ctx: u32,
+}
+
+impl GlslToken {
+    const WHITESPACE: u8 = 0;
+    const WORD: u8 = 1;
+    const OPERATOR: u8 = 2;
+    /// Brackets and separators are always tokens of their own
+    const PUNCTUATION: u8 = 3;
+
+    pub fn new(hash_table: Rc<RefCell<HashTable<NOrderByteData>>>) -> Self {
+        Self {
+            ctx: 0,
+            bit_ctx: 1,
+            magic_num: hash(0x6151, 2),
+            slots: ContextSlots::new(hash_table),
+            token: FNV_BASIS,
+            token_class: Self::WHITESPACE,
+            prev_tokens: [FNV_BASIS; 2],
+        }
+    }
+
+    fn char_class(byte: u8) -> u8 {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'_' | b'#' => Self::WORD,
+            b'(' | b')' | b'[' | b']' | b'{' | b'}' | b';' | b',' | b'.' => Self::PUNCTUATION,
+            b'+' | b'-' | b'*' | b'/' | b'%' | b'=' | b'<' | b'>' | b'!' | b'&' | b'|' | b'^'
+            | b'~' | b'?' | b':' => Self::OPERATOR,
+            _ => Self::WHITESPACE,
+        }
+    }
+
+    fn update(&mut self, byte: u8) {
+        let class = Self::char_class(byte);
+        if self.token_class != Self::WHITESPACE
+            && (class != self.token_class || class == Self::PUNCTUATION)
+        {
+            self.prev_tokens = [self.token, self.prev_tokens[0]];
+            self.token = FNV_BASIS;
+        }
+
+        self.token_class = class;
+        if class != Self::WHITESPACE {
+            self.token = (self.token ^ byte as u32).wrapping_mul(16777619);
+        }
+    }
+}
+
+impl BitHistoryCounters for GlslToken {
+    fn with_bit_history(mut self) -> Self {
+        self.slots.use_bit_history(&mut self.magic_num);
+        self
+    }
+}
+
+impl Model for GlslToken {
+    fn pred(&mut self) -> f64 {
+        self.slots.pred(self.ctx ^ self.bit_ctx)
+    }
+
+    fn learn(&mut self, bit: u8) {
+        self.slots.learn(self.ctx ^ self.bit_ctx, bit);
+
+        self.bit_ctx = (self.bit_ctx << 1) | bit as u32;
+        if self.bit_ctx >= 256 {
+            self.update(self.bit_ctx as u8);
+
+            let [prev1, prev2] = self.prev_tokens;
+            let prev_tokens = prev1.wrapping_mul(0x2545F491) ^ prev2;
+            self.ctx = norder_hash(
+                ((prev_tokens as u64) << 32) | self.token as u64,
+                self.magic_num,
+            );
+
+            self.bit_ctx = 1;
+        }
+    }
+}
+
+/// Model for numeric literals in text, e.g. minified code and JSON
+/// Inside a number it uses the digit position, the decimal point and exponent
+/// and the shape of the previous number as context. Predicts nothing outside numbers.
+pub struct Number {
+    contexts: MixedContexts,
+
+    magic_num: u32,
+    in_number: bool,
+    int_digits: u32,
+    frac_digits: u32,
+    has_point: bool,
+    /// 0: no exponent, 1: after `e`, 2: after the exponent sign, 3: in exponent digits
+    exp_state: u32,
+    exp_digits: u32,
+    /// Byte preceding the number, e.g. `-` or `,`
+    start_byte: u8,
+    prev_shape: u32,
+    prev_bytes: u32,
+
+    bit_ctx: u32,
+}
+
+impl Number {
+    pub fn new(hash_table: Rc<RefCell<HashTable<NOrderByteData>>>) -> Self {
+        Self {
+            contexts: MixedContexts::new(hash_table, 3),
+            magic_num: hash(0x9e30, 2),
+            in_number: false,
+            int_digits: 0,
+            frac_digits: 0,
+            has_point: false,
+            exp_state: 0,
+            exp_digits: 0,
+            start_byte: 0,
+            prev_shape: 0,
+            prev_bytes: 0,
+            bit_ctx: 1,
+        }
+    }
+
+    fn shape(&self) -> u32 {
+        self.int_digits.min(15)
+            | (self.frac_digits.min(15) << 4)
+            | ((self.has_point as u32) << 8)
+            | (self.exp_state << 9)
+            | (self.exp_digits.min(3) << 11)
+    }
+
+    fn update(&mut self, byte: u8) {
+        let prev_byte = self.prev_bytes as u8;
+        self.prev_bytes = (self.prev_bytes << 8) | byte as u32;
+
+        if self.in_number {
+            match byte {
+                b'0'..=b'9' if self.exp_state > 0 => {
+                    self.exp_state = 3;
+                    self.exp_digits += 1;
+                }
+                b'0'..=b'9' if self.has_point => self.frac_digits += 1,
+                b'0'..=b'9' => self.int_digits += 1,
+                b'.' if !self.has_point && self.exp_state == 0 => self.has_point = true,
+                b'e' | b'E' if self.exp_state == 0 => self.exp_state = 1,
+                b'+' | b'-' if self.exp_state == 1 => self.exp_state = 2,
+                _ => {
+                    self.prev_shape = self.shape();
+                    self.in_number = false;
+                }
+            }
+        } else if byte.is_ascii_digit()
+            // Digits of identifiers like `vec3` aren't numbers
+            && !(prev_byte.is_ascii_alphanumeric() || prev_byte == b'_' || prev_byte == b'$')
+        {
+            // Numbers like `.5` start with the decimal point
+            let has_point = prev_byte == b'.';
+            self.in_number = true;
+            self.int_digits = !has_point as u32;
+            self.frac_digits = has_point as u32;
+            self.has_point = has_point;
+            self.exp_state = 0;
+            self.exp_digits = 0;
+            self.start_byte = (self.prev_bytes >> (8 + 8 * has_point as u32)) as u8;
+        }
+    }
+}
+
+impl BitHistoryCounters for Number {
+    fn with_bit_history(mut self) -> Self {
+        self.contexts.slots.use_bit_history(&mut self.magic_num);
+        self
+    }
 }
 
-pub struct HashTable<Record> {
-    table: Vec<Record>,
-    hash_mask: usize,
+impl Model for Number {
+    fn pred(&mut self) -> f64 {
+        if !self.in_number {
+            return 0.;
+        }
+
+        self.contexts.pred(self.bit_ctx)
+    }
+
+    fn learn(&mut self, bit: u8) {
+        if self.in_number {
+            self.contexts.learn(self.bit_ctx, bit);
+        }
+
+        self.bit_ctx = (self.bit_ctx << 1) | bit as u32;
+        if self.bit_ctx >= 256 {
+            self.update(self.bit_ctx as u8);
+            if self.in_number {
+                let shape = self.shape();
+                self.contexts.set_ctxs(
+                    &[
+                        shape | (self.prev_shape << 16),
+                        shape | ((self.prev_bytes & 0xffff) << 16),
+                        shape | ((self.start_byte as u32) << 16) | ((self.prev_bytes & 0xff) << 24),
+                    ],
+                    0,
+                    self.magic_num,
+                );
+            }
+
+            self.bit_ctx = 1;
+        }
+    }
 }
 
-fn hash(mut value: u32, shift: u32) -> u32 {
-    const K_MUL: u32 = 0x9E35A7BD;
-    value ^= value >> shift;
-    K_MUL.wrapping_mul(value) >> shift
+/// Largest number of floats per element of the float32 model
+pub const MAX_FLOAT32_STRIDE: u32 = 256;
+
+/// Model for little-endian float32 arrays
+/// Uses the position within the float (mantissa vs sign/exponent bytes)
+/// and the corresponding bytes of the previous elements as context,
+/// where an element consists of `stride` floats, e.g. 3 for xyz positions.
+pub struct Float32 {
+    contexts: MixedContexts,
+
+    magic_num: u32,
+    stride_size: u32,
+
+    /// Previous bytes, enough to look back two elements
+    history: Vec<u8>,
+    pos: u32,
+
+    bit_ctx: u32,
 }
 
-impl<Record> HashTable<Record>
-where
-    Record: Default + Clone,
-{
-    pub fn new(pow2_size: u32) -> Self {
-        let context_size = (1 << pow2_size) as usize;
-        println!(
-            "Hash table Size: {} MiB",
-            (size_of::<Record>() * context_size) / (1024 * 1024)
-        );
+impl Float32 {
+    pub fn new(stride: u32, hash_table: Rc<RefCell<HashTable<NOrderByteData>>>) -> Self {
+        assert!((1..=MAX_FLOAT32_STRIDE).contains(&stride));
 
         Self {
-            table: vec![Record::default(); context_size],
-            hash_mask: context_size - 1,
+            contexts: MixedContexts::new(hash_table, 6),
+            magic_num: hash(0xf320, 2),
+            stride_size: stride * 4,
+            history: vec![0; (8 * stride + 4).next_power_of_two() as usize],
+            pos: 0,
+            bit_ctx: 1,
         }
     }
 
-    #[allow(dead_code)]
-    pub fn len(&self) -> usize {
-        self.table.len()
+    fn float_at(&self, start: u32) -> u32 {
+        let history_mask = self.history.len() - 1;
+        u32::from_le_bytes(std::array::from_fn(|i| {
+            self.history[start.wrapping_add(i as u32) as usize & history_mask]
+        }))
     }
 
-    pub fn get<'a>(&'a self, key: u32) -> &'a Record {
-        &self.table[key as usize & self.hash_mask]
+    fn update_ctxs(&mut self) {
+        let byte_idx = self.pos % 4;
+        let start = self.pos - byte_idx;
+        let prev1 = self.float_at(start.wrapping_sub(self.stride_size));
+        let prev2 = self.float_at(start.wrapping_sub(2 * self.stride_size));
+        let adjacent = self.float_at(start.wrapping_sub(4));
+        // Lower bytes of the current float, which are already known
+        let current = self.float_at(start) & ((1u64 << (8 * byte_idx)) - 1) as u32;
+
+        // Linear extrapolation of the previous elements
+        let extrapolated =
+            (2. * f32::from_bits(prev1) as f64 - f32::from_bits(prev2) as f64) as f32;
+        let extrapolated = if extrapolated.is_nan() {
+            0
+        } else {
+            extrapolated.to_bits()
+        };
+
+        // The byte being coded and the more significant ones
+        let high = |value: u32| value >> (8 * byte_idx);
+        let byte = |value: u32| high(value) & 0xff;
+        let prev_byte = if byte_idx > 0 { byte(current << 8) } else { 0 };
+        let values = [
+            high(prev1),
+            byte(prev1) | (byte(prev2) << 8),
+            high(extrapolated),
+            byte(prev1) | (byte(adjacent) << 8),
+            // Sign, exponent and highest mantissa bits of the previous element
+            (prev1 >> 16) | (prev_byte << 16),
+            byte(adjacent) | (prev_byte << 8) | (byte(prev1) << 16),
+        ];
+        self.contexts.set_ctxs(&values, byte_idx, self.magic_num);
     }
+}
 
-    pub fn get_mut<'a>(&'a mut self, key: u32) -> &'a mut Record {
-        &mut self.table[key as usize & self.hash_mask]
+impl BitHistoryCounters for Float32 {
+    fn with_bit_history(mut self) -> Self {
+        self.contexts.slots.use_bit_history(&mut self.magic_num);
+        self
     }
 }
 
-/// NOrderByte model for byte predictions
-/// Can describe [0, 8] order models and partial models
-/// It also supports being a word model
-/// (using characters as window filters)
-pub struct NOrderByte {
-    ctx: u32,
-    hash_table: Rc<RefCell<HashTable<NOrderByteData>>>,
-    max_count: u32,
+impl Model for Float32 {
+    fn pred(&mut self) -> f64 {
+        self.contexts.pred(self.bit_ctx)
+    }
 
-    magic_num: u32,
-    prev_bytes: u64,
-    mask: u64,
-    is_word_model: bool,
+    fn learn(&mut self, bit: u8) {
+        self.contexts.learn(self.bit_ctx, bit);
+
+        self.bit_ctx = (self.bit_ctx << 1) | bit as u32;
+        if self.bit_ctx >= 256 {
+            let history_mask = self.history.len() - 1;
+            self.history[self.pos as usize & history_mask] = self.bit_ctx as u8;
+            self.pos = self.pos.wrapping_add(1);
+            self.update_ctxs();
+
+            self.bit_ctx = 1;
+        }
+    }
+}
 
+/// Largest number of hidden cells of the recurrent model
+pub const MAX_GRU_CELLS: u32 = 256;
+
+/// Small byte-level recurrent network (GRU) that learns online
+/// The previous byte updates the hidden state, which predicts the bits of the next byte.
+/// Activations avoid exp so the JS decoder gets identical results.
+pub struct Gru {
+    cells: usize,
+    rng: u32,
+
+    /// Weights of the previous byte for the update gate, reset gate and candidate,
+    /// laid out as [byte][gate][cell]
+    input_weights: Vec<f64>,
+    /// Weights of the hidden state, laid out as [gate][cell][cell]
+    recurrent_weights: Vec<f64>,
+    /// Weights of the hidden state per partial byte, laid out as [bit_ctx][cell]
+    output_weights: Vec<f64>,
+    output_biases: Vec<f64>,
+
+    hidden: Vec<f64>,
+    // Values of the last step needed for training it
+    prev_hidden: Vec<f64>,
+    reset_hidden: Vec<f64>,
+    update_gate: Vec<f64>,
+    candidate: Vec<f64>,
+    /// Derivatives of the gate and candidate activations, laid out as [gate][cell]
+    activation_grads: Vec<f64>,
+    /// Gradient of the hidden state, summed over the bits of the byte
+    hidden_grad: Vec<f64>,
+    /// Gradients of the gate and candidate inputs, laid out as [gate][cell]
+    gate_grads: Vec<f64>,
+
+    prev_byte: u8,
+    logit: f64,
     bit_ctx: u32,
 }
 
-impl NOrderByte {
-    pub fn new_norder_model(
-        byte_mask: u8,
-        hash_table: Rc<RefCell<HashTable<NOrderByteData>>>,
-        max_count: u32,
-    ) -> Self {
-        assert!(max_count <= 255);
+impl Gru {
+    const UPDATE: usize = 0;
+    const RESET: usize = 1;
+    const CANDIDATE: usize = 2;
+
+    const OUTPUT_LEARNING_RATE: f64 = 0.5;
+    const LEARNING_RATE: f64 = 0.2;
+
+    pub fn new(cells: u32) -> Self {
+        assert!((1..=MAX_GRU_CELLS).contains(&cells));
+        let cells = cells as usize;
+
+        let mut gru = Self {
+            cells,
+            rng: 0x2545F491,
+            input_weights: vec![0.; 256 * 3 * cells],
+            recurrent_weights: vec![0.; 3 * cells * cells],
+            output_weights: vec![0.; 256 * cells],
+            output_biases: vec![0.; 256],
+            hidden: vec![0.; cells],
+            prev_hidden: vec![0.; cells],
+            reset_hidden: vec![0.; cells],
+            update_gate: vec![0.; cells],
+            candidate: vec![0.; cells],
+            activation_grads: vec![0.; 3 * cells],
+            hidden_grad: vec![0.; cells],
+            gate_grads: vec![0.; 3 * cells],
+            prev_byte: 0,
+            logit: 0.,
+            bit_ctx: 1,
+        };
 
-        let mut bit_mask: u64 = 0;
-        for i in 0..8 {
-            bit_mask |= ((byte_mask >> i) & 1) as u64 * (0xff << (i * 8));
+        // Deterministic random weights, to break the symmetry between cells
+        for i in 0..gru.input_weights.len() {
+            gru.input_weights[i] = gru.random_weight(1.);
+        }
+        let scale = 2. / (cells as f64).sqrt();
+        for i in 0..gru.recurrent_weights.len() {
+            gru.recurrent_weights[i] = gru.random_weight(scale);
         }
 
-        Self {
-            ctx: 0,
-            bit_ctx: 1,
-            magic_num: hash(byte_mask as u32, 2),
-            max_count: 15,
-            hash_table: hash_table,
-            prev_bytes: 0,
-            mask: bit_mask,
-            is_word_model: false,
+        gru
+    }
+
+    /// Uniform in [-scale / 2, scale / 2), from a xorshift generator
+    fn random_weight(&mut self, scale: f64) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 17;
+        self.rng ^= self.rng << 5;
+        (self.rng as f64 / 4294967296. - 0.5) * scale
+    }
+
+    fn softsign(x: f64) -> f64 {
+        x / (1. + x.abs())
+    }
+
+    /// Logistic function of `x` in [-16, 16], computed with basic arithmetic only,
+    /// since `exp` differs in the last bits between Rust and JS
+    fn squash(x: f64) -> f64 {
+        use std::f64::consts::LN_2;
+        let k = (-x / LN_2 + 0.5).floor();
+        let r = -x - k * LN_2;
+        // exp(r) for |r| <= ln(2) / 2
+        let mut exp = 1.;
+        for i in (1..=10).rev() {
+            exp = 1. + exp * r / i as f64;
         }
+        let pow2 = (1u32 << k.abs() as u32) as f64;
+        let exp = if k < 0. { exp / pow2 } else { exp * pow2 };
+        1. / (1. + exp)
     }
 
-    pub fn new_word_model(
-        hash_table: Rc<RefCell<HashTable<NOrderByteData>>>,
-        _max_count: u32,
-    ) -> Self {
-        Self {
-            ctx: 0,
-            bit_ctx: 1,
-            magic_num: hash(1337 as u32, 2),
-            max_count: 15,
-            hash_table: hash_table,
-            prev_bytes: 2166136261,
-            mask: u64::MAX,
-            is_word_model: true,
+    fn softsign_grad(x: f64) -> f64 {
+        let d = 1. + x.abs();
+        1. / (d * d)
+    }
+
+    /// Sum of the input weight and the recurrent weights of `gate` times `values`
+    fn gate_input(&self, gate: usize, cell: usize, values: &[f64]) -> f64 {
+        let cells = self.cells;
+        let weights = &self.recurrent_weights[(gate * cells + cell) * cells..][..cells];
+        let mut sum = self.input_weights[(self.prev_byte as usize * 3 + gate) * cells + cell];
+        for j in 0..cells {
+            sum += weights[j] * values[j];
         }
+        sum
     }
-}
 
-impl Model for NOrderByte {
-    fn pred(&mut self) -> f64 {
-        let entry = self
-            .hash_table
-            .borrow()
-            .get(self.ctx ^ self.bit_ctx)
-            .clone();
+    /// Updates the hidden state with `prev_byte`
+    fn step(&mut self) {
+        let cells = self.cells;
+        self.prev_hidden.copy_from_slice(&self.hidden);
+
+        for i in 0..cells {
+            let update = self.gate_input(Self::UPDATE, i, &self.prev_hidden);
+            let reset = self.gate_input(Self::RESET, i, &self.prev_hidden);
+            self.update_gate[i] = 0.5 + 0.5 * Self::softsign(update);
+            self.activation_grads[Self::UPDATE * cells + i] = 0.5 * Self::softsign_grad(update);
+            self.reset_hidden[i] = (0.5 + 0.5 * Self::softsign(reset)) * self.prev_hidden[i];
+            self.activation_grads[Self::RESET * cells + i] = 0.5 * Self::softsign_grad(reset);
+        }
 
-        prob_stretch(entry.prob() as f64 / U24_MAX as f64)
+        for i in 0..cells {
+            let candidate = self.gate_input(Self::CANDIDATE, i, &self.reset_hidden);
+            self.candidate[i] = Self::softsign(candidate);
+            self.activation_grads[Self::CANDIDATE * cells + i] = Self::softsign_grad(candidate);
+            self.hidden[i] = self.prev_hidden[i]
+                + self.update_gate[i] * (self.candidate[i] - self.prev_hidden[i]);
+        }
     }
 
-    fn learn(&mut self, bit: u8) {
-        {
-            let mut hash_table = self.hash_table.borrow_mut();
-            let inst = hash_table.get_mut(self.ctx ^ self.bit_ctx);
+    /// Trains the last step with the gradient of the hidden state,
+    /// without propagating it further back in time
+    fn train_step(&mut self) {
+        let cells = self.cells;
+        let input_base = self.prev_byte as usize * 3 * cells;
+
+        let grads = &mut self.gate_grads;
+        for i in 0..cells {
+            let grad = self.hidden_grad[i];
+            grads[Self::UPDATE * cells + i] = grad
+                * (self.candidate[i] - self.prev_hidden[i])
+                * self.activation_grads[Self::UPDATE * cells + i];
+            grads[Self::CANDIDATE * cells + i] =
+                grad * self.update_gate[i] * self.activation_grads[Self::CANDIDATE * cells + i];
+        }
 
-            let (mut count, mut prob) = (inst.count(), inst.prob());
-            if count < self.max_count {
-                count += 1;
+        for j in 0..cells {
+            let mut sum = 0.;
+            for i in 0..cells {
+                sum += grads[Self::CANDIDATE * cells + i]
+                    * self.recurrent_weights[(Self::CANDIDATE * cells + i) * cells + j];
             }
+            grads[Self::RESET * cells + j] =
+                sum * self.prev_hidden[j] * self.activation_grads[Self::RESET * cells + j];
+        }
 
-            let count_sqrt = count as f64 + 0.2;
-            // Learning function
-            prob += (U24_MAX as f64 * ((bit as f64 - (prob as f64 / U24_MAX as f64)) / count_sqrt))
-                as i32;
+        for gate in [Self::UPDATE, Self::RESET, Self::CANDIDATE] {
+            let values = if gate == Self::CANDIDATE {
+                &self.reset_hidden
+            } else {
+                &self.prev_hidden
+            };
+            for i in 0..cells {
+                let step = Self::LEARNING_RATE * grads[gate * cells + i];
+                self.input_weights[input_base + gate * cells + i] += step;
+                let weights = &mut self.recurrent_weights[(gate * cells + i) * cells..][..cells];
+                for (weight, value) in weights.iter_mut().zip(values) {
+                    *weight += step * value;
+                }
+            }
+        }
 
-            inst.set_count(count);
-            inst.set_prob(prob);
+        self.hidden_grad.fill(0.);
+    }
+}
+
+impl Model for Gru {
+    fn pred(&mut self) -> f64 {
+        let weights = &self.output_weights[self.bit_ctx as usize * self.cells..][..self.cells];
+        let mut sum = self.output_biases[self.bit_ctx as usize];
+        for (weight, hidden) in weights.iter().zip(&self.hidden) {
+            sum += weight * hidden;
+        }
+
+        self.logit = sum.clamp(-16., 16.);
+        self.logit
+    }
+
+    fn learn(&mut self, bit: u8) {
+        let err = bit as f64 - Self::squash(self.logit);
+        self.output_biases[self.bit_ctx as usize] += Self::OUTPUT_LEARNING_RATE * err;
+        let weights = &mut self.output_weights[self.bit_ctx as usize * self.cells..][..self.cells];
+        for ((grad, weight), hidden) in self.hidden_grad.iter_mut().zip(weights).zip(&self.hidden) {
+            *grad += err * *weight;
+            *weight += Self::OUTPUT_LEARNING_RATE * err * hidden;
         }
 
         self.bit_ctx = (self.bit_ctx << 1) | bit as u32;
         if self.bit_ctx >= 256 {
-            let current_byte = self.bit_ctx & 0xff;
+            self.train_step();
+            self.prev_byte = self.bit_ctx as u8;
+            self.step();
 
-            if self.is_word_model {
-                let next_char = current_byte as u8 as char;
-                if next_char.is_ascii_alphanumeric() {
-                    self.prev_bytes = self.prev_bytes ^ next_char.to_ascii_lowercase() as u64;
-                    self.prev_bytes = self.prev_bytes.wrapping_mul(16777619) >> 16;
-                } else {
-                    self.prev_bytes = 2166136261;
-                }
-            } else {
-                self.prev_bytes = (self.prev_bytes << 8) | current_byte as u64;
-            }
+            self.bit_ctx = 1;
+        }
+    }
+}
 
-            let masked_prev_bytes = self.prev_bytes & self.mask;
-            self.ctx = (hash((masked_prev_bytes >> 32) as u32, 3)
-                .wrapping_mul(9)
-                .wrapping_add(hash(masked_prev_bytes as u32, 3)))
-            .wrapping_add(1) // To ensure ctx doesn't overlap between models
-            .wrapping_mul(self.magic_num);
+/// Restricts a model to a byte range of the input, e.g. a single file.
+/// The model only sees the bytes of the range and predicts nothing outside of it.
+pub struct InRange {
+    start: u32,
+    end: u32,
+    pos: u32,
+    bit_ctx: u32,
+    model: Box<dyn Model>,
+}
 
-            // Reset bit_ctx
+impl InRange {
+    pub fn new(range: std::ops::Range<u32>, model: Box<dyn Model>) -> Self {
+        Self {
+            start: range.start,
+            end: range.end,
+            pos: 0,
+            bit_ctx: 1,
+            model,
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        (self.start..self.end).contains(&self.pos)
+    }
+}
+
+impl Model for InRange {
+    fn pred(&mut self) -> f64 {
+        if self.is_active() {
+            self.model.pred()
+        } else {
+            0.
+        }
+    }
+
+    fn learn(&mut self, bit: u8) {
+        if self.is_active() {
+            self.model.learn(bit);
+        }
+
+        self.bit_ctx = (self.bit_ctx << 1) | bit as u32;
+        if self.bit_ctx >= 256 {
+            self.pos += 1;
             self.bit_ctx = 1;
         }
     }
@@ -245,7 +2032,7 @@ impl Model for LnMixerPred {
             let model_weight = if weights.is_empty() {
                 model.weight
             } else {
-                f64::mul_add(weights[i], 0.3, model.weight)
+                weights[i] * 0.3 + model.weight
             };
 
             let p = model.model.pred();
@@ -297,6 +2084,16 @@ impl Model for LnMixerPred {
 #[derive(Clone, Default)]
 pub struct SSEPredData([NOrderByteData; 32]);
 
+impl HashRecord for SSEPredData {
+    fn priority(&self) -> u32 {
+        self.0
+            .iter()
+            .map(|counter| counter.count())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
 impl Index<usize> for SSEPredData {
     type Output = NOrderByteData;
 
@@ -311,43 +2108,44 @@ impl IndexMut<usize> for SSEPredData {
     }
 }
 
-pub struct AdaptiveProbabilityMap {
+/// A single APM (SSE) stage refining an input probability
+/// using an order-N byte context
+pub struct ApmStage {
     ctx: u32,
     hash_table: HashTable<SSEPredData>,
     max_count: u32,
+    rate: u32,
 
     current_prob_idx: usize,
-
-    prev_bytes: u64,
     mask: u64,
-
-    bit_ctx: u32,
-
-    input_model: Box<dyn Model>,
+    // Unused counters start out mapping each bucket to its own probability
+    init_probs: [i32; 32],
 }
 
-impl AdaptiveProbabilityMap {
-    pub fn new(pow2_size: u32, input_model: Box<dyn Model>) -> AdaptiveProbabilityMap {
-        AdaptiveProbabilityMap {
+impl ApmStage {
+    pub fn new(pow2_size: u32, order: u32, rate: u32) -> ApmStage {
+        assert!(order <= 8);
+
+        ApmStage {
             ctx: 0,
             hash_table: HashTable::<SSEPredData>::new(pow2_size),
             max_count: 255,
+            rate,
             current_prob_idx: 0,
-
-            prev_bytes: 0,
-            mask: 0xffffff,
-
-            bit_ctx: 1,
-            input_model: input_model,
+            mask: if order >= 8 {
+                u64::MAX
+            } else {
+                (1 << (order * 8)) - 1
+            },
+            init_probs: std::array::from_fn(|idx| {
+                (prob_squash((idx as f64 - 16.) / 2.) * U24_MAX as f64) as i32
+            }),
         }
     }
-}
 
-impl Model for AdaptiveProbabilityMap {
-    fn pred(&mut self) -> f64 {
-        let p = self.input_model.pred();
-        // TODO: Interpolate probabilities
-        let p_ptr = p.max(-8.).min(7.5) * 2.;
+    /// Maps the stretched input probability to a refined probability in [0, 1]
+    fn refine(&mut self, p: f64, bit_ctx: u32) -> f64 {
+        let p_ptr = p.clamp(-8., 7.5) * 2.;
         let p_idx_f = p_ptr.floor();
         let p_idx_c = p_ptr.ceil();
 
@@ -369,65 +2167,92 @@ impl Model for AdaptiveProbabilityMap {
             next_idx = self.current_prob_idx - 1;
             t = 1. - delta_c;
         }
-        if next_idx >= 32 {
-            println!(
-                "Warning: next_idx is out of bounds: {}, {}, {}, {}, {}",
-                next_idx, self.current_prob_idx, t, p, p_ptr
-            );
-        }
 
-        let counter1 = {
-            let counter1: &mut NOrderByteData =
-                &mut self.hash_table.get_mut(self.ctx ^ self.bit_ctx)[self.current_prob_idx];
-            if counter1.count() == 0 {
-                let prob = (prob_squash(p) * U24_MAX as f64) as i32 & U24_MAX as i32;
-                counter1.set_prob(prob);
+        let counters = self.hash_table.get_mut(self.ctx ^ bit_ctx);
+        for idx in [self.current_prob_idx, next_idx] {
+            if counters[idx].count() == 0 {
+                counters[idx].set_prob(self.init_probs[idx]);
             }
-            counter1.clone()
-        };
+        }
 
-        let counter2 = {
-            let counter2: &mut NOrderByteData =
-                &mut self.hash_table.get_mut(self.ctx ^ self.bit_ctx)[next_idx];
-            if counter2.count() == 0 {
-                let prob = (prob_squash(p) * U24_MAX as f64) as i32 & U24_MAX as i32;
-                counter2.set_prob(prob);
-            }
-            counter2.clone()
-        };
+        t * (counters[self.current_prob_idx].prob() as f64 / U24_MAX as f64)
+            + (1. - t) * (counters[next_idx].prob() as f64 / U24_MAX as f64)
+    }
+
+    fn learn(&mut self, bit: u8, bit_ctx: u32) {
+        let inst = &mut self.hash_table.get_mut(self.ctx ^ bit_ctx)[self.current_prob_idx];
 
-        let new_p = t * (counter1.prob() as f64 / U24_MAX as f64)
-            + (1. - t) * (counter2.prob() as f64 / U24_MAX as f64);
-        prob_stretch(new_p)
+        let (mut count, mut prob) = (inst.count(), inst.prob());
+        if count < self.max_count {
+            count += 1;
+        }
+
+        // Learning function
+        prob += (U24_MAX as f64
+            * ((bit as f64 - (prob as f64 / U24_MAX as f64)) / ((count + self.rate) as f64 + 1.5)))
+            as i32;
+        inst.set_count(count);
+        inst.set_prob(prob);
     }
 
-    fn learn(&mut self, bit: u8) {
-        {
-            let inst = &mut self.hash_table.get_mut(self.ctx ^ self.bit_ctx)
-                [self.current_prob_idx as usize];
+    fn update_ctx(&mut self, prev_bytes: u64) {
+        let masked_prev_bytes = prev_bytes & self.mask;
+        self.ctx = hash((masked_prev_bytes >> 32) as u32, 3)
+            .wrapping_mul(9)
+            .wrapping_add(hash(masked_prev_bytes as u32, 3));
+    }
+}
 
-            let (mut count, mut prob) = (inst.count(), inst.prob());
-            if count < self.max_count {
-                count += 1;
-            }
+/// Refines the prediction of the input model with one or more APM stages.
+/// When several stages are used, their outputs are averaged.
+/// Stages can be chained by using another AdaptiveProbabilityMap as input model.
+pub struct AdaptiveProbabilityMap {
+    stages: Vec<ApmStage>,
 
-            // Learning function
-            prob += (U24_MAX as f64
-                * ((bit as f64 - (prob as f64 / U24_MAX as f64)) / ((count + 30) as f64 + 1.5)))
-                as i32;
-            inst.set_count(count);
-            inst.set_prob(prob);
+    prev_bytes: u64,
+    bit_ctx: u32,
+
+    input_model: Box<dyn Model>,
+}
+
+impl AdaptiveProbabilityMap {
+    pub fn new(stages: Vec<ApmStage>, input_model: Box<dyn Model>) -> AdaptiveProbabilityMap {
+        assert!(!stages.is_empty());
+
+        AdaptiveProbabilityMap {
+            stages,
+            prev_bytes: 0,
+            bit_ctx: 1,
+            input_model,
+        }
+    }
+}
+
+impl Model for AdaptiveProbabilityMap {
+    fn pred(&mut self) -> f64 {
+        let p = self.input_model.pred();
+
+        let mut sum = 0.;
+        for stage in &mut self.stages {
+            sum += stage.refine(p, self.bit_ctx);
+        }
+
+        prob_stretch(sum / self.stages.len() as f64)
+    }
+
+    fn learn(&mut self, bit: u8) {
+        for stage in &mut self.stages {
+            stage.learn(bit, self.bit_ctx);
         }
 
         self.bit_ctx = (self.bit_ctx << 1) | bit as u32;
         if self.bit_ctx >= 256 {
             self.bit_ctx &= 0xff;
 
-            self.prev_bytes = ((self.prev_bytes << 8) | self.bit_ctx as u64) & self.mask;
-            // Remove the extra leading bit before using it in the ctx
-            self.ctx = hash((self.prev_bytes >> 32) as u32, 3)
-                .wrapping_mul(9)
-                .wrapping_add(hash(self.prev_bytes as u32, 3));
+            self.prev_bytes = (self.prev_bytes << 8) | self.bit_ctx as u64;
+            for stage in &mut self.stages {
+                stage.update_ctx(self.prev_bytes);
+            }
 
             // Reset bit_ctx
             self.bit_ctx = 1;
@@ -436,3 +2261,66 @@ impl Model for AdaptiveProbabilityMap {
         self.input_model.learn(bit);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::{HashTable, Model, NOrderByteData, Record, BUCKET_SIZE};
+
+    fn learn_bytes(model: &mut impl Model, bytes: &[u8]) {
+        for byte in bytes {
+            for i in (0..8).rev() {
+                model.pred();
+                model.learn((byte >> i) & 1);
+            }
+        }
+    }
+
+    /// Records of `length` bytes with a counter in the first byte
+    fn records(length: usize, count: usize) -> Vec<u8> {
+        (0..count)
+            .flat_map(|i| (0..length).map(move |j| if j == 0 { i as u8 } else { j as u8 * 11 }))
+            .collect()
+    }
+
+    #[test]
+    pub fn record_detects_length_per_file() {
+        let first = records(6, 100);
+        let second = records(10, 100);
+        let hash_table = Rc::new(RefCell::new(HashTable::new(16)));
+        let mut record = Record::new(0, 1, vec![0, first.len() as u32], hash_table);
+
+        let (last, rest) = first.split_last().unwrap();
+        learn_bytes(&mut record, rest);
+        assert_eq!(record.length, 6);
+        // Detection starts over when the second file starts
+        learn_bytes(&mut record, &[*last]);
+        assert_eq!(record.length, 0);
+        learn_bytes(&mut record, &second);
+        assert_eq!(record.length, 10);
+    }
+
+    #[test]
+    pub fn tags_use_bits_outside_the_index() {
+        type Table = HashTable<NOrderByteData>;
+        for pow2_size in [20, 26, 28, 30] {
+            // Keys of the same bucket, which differ in the low 2 bits
+            // and at most 6 bits above the index
+            let keys: Vec<u32> = (0..256u32)
+                .map(|bits| {
+                    ((bits >> 2) << pow2_size) | (bits & 3) | 0x1234 & !(BUCKET_SIZE as u32 - 1)
+                })
+                .collect();
+            let mut tags: Vec<u8> = keys.iter().map(|&key| Table::tag(key, pow2_size)).collect();
+            tags.sort_unstable();
+            tags.dedup();
+            assert_eq!(
+                tags.len(),
+                keys.iter().collect::<std::collections::BTreeSet<_>>().len(),
+                "{}",
+                pow2_size
+            );
+        }
+    }
+}
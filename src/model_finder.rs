@@ -1,7 +1,7 @@
 use std::{cell::RefCell, rc::Rc};
 
 use crate::{
-    compress_config::ModelConfig,
+    compress_config::{CounterType, HashTables, ModelConfig},
     model::{HashTable, Model, NOrderByteData},
 };
 
@@ -17,7 +17,10 @@ impl ModelFinder {
 
         Self {
             default_model: model
-                .create_model(Rc::new(RefCell::new(HashTable::<NOrderByteData>::new(26))))
+                .create_model(&HashTables::from([(
+                    None,
+                    Rc::new(RefCell::new(HashTable::<NOrderByteData>::new(26))),
+                )]))
                 .unwrap(),
         }
     }
@@ -49,10 +52,15 @@ pub fn create_default_model_config() -> ModelConfig {
         .into_iter()
         .map(|mask| ModelConfig::NOrderByte {
             byte_mask: format!("0b{:08b}", mask),
+            table: None,
+            counter: CounterType::Probability,
         })
         .collect::<Vec<_>>();
 
-    mixed_models.push(ModelConfig::Word);
+    mixed_models.push(ModelConfig::Word {
+        table: None,
+        counter: CounterType::Probability,
+    });
 
     ModelConfig::Mixer {
         models: mixed_models.clone(),
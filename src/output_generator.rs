@@ -1,12 +1,17 @@
 use std::{
     fs,
     io::{BufWriter, Write},
+    ops::Range,
     path::PathBuf,
     process::{Command, Stdio},
 };
 
-use crate::compress_config::ModelConfig;
-use anyhow::{anyhow, Context, Result};
+use crate::{
+    compress_config::{CounterType, HashTableLayouts, ModelConfig},
+    filter::Filter,
+    word_transform::WordTransform,
+};
+use anyhow::{anyhow, ensure, Context, Result};
 use bitflags::bitflags;
 use clap::ValueEnum;
 use handlebars::Handlebars;
@@ -18,6 +23,15 @@ pub struct OutputGenerationOptions {
     pub output_dir: PathBuf,
     pub target: Target,
     pub model_config: ModelConfig,
+    pub hash_tables: HashTableLayouts,
+    /// Minified decompressor source the model was warmed up with, see [`prime_source`]
+    pub prime_source: Option<String>,
+    /// Priming dictionary the model was warmed up with after `prime_source`
+    pub dictionary: Option<String>,
+    /// Word transformed sections, by their range in the decoded data
+    pub word_transforms: Vec<(Range<u32>, WordTransform)>,
+    /// Filtered files, by their range in the decoded data after the word transforms are undone
+    pub filters: Vec<(Range<u32>, Vec<Filter>)>,
 }
 
 bitflags! {
@@ -30,27 +44,129 @@ bitflags! {
         const AdaptiveProbabilityMap = 0b00000100;
         const Word = 0b00001000;
         const HashTable = 0b00010000;
+        const BitHistory = 0b00100000;
+        const Indirect = 0b01000000;
+        const Record = 0b10000000;
+        const Image = 0b100000000;
+        const InFile = 0b1000000000;
+        const Audio = 0b10000000000;
+        const Float32 = 0b100000000000;
+        const Column = 0b1000000000000;
+        const JsLexer = 0b10000000000000;
+        const GlslToken = 0b100000000000000;
+        const Number = 0b1000000000000000;
+        const Gru = 0b10000000000000000;
     }
 }
 
+/// Minified decompressor source (models and decoder) to warm up the model with.
+/// It starts the boot script, so the decoder finds it in the inflated script at runtime.
+pub fn prime_source(model_config: &ModelConfig, hash_tables: &HashTableLayouts) -> Result<String> {
+    let mut features_used = ModelRef::None;
+    // Minified on its own, so it keeps the top level names that the boot script calls
+    let source = uglify_keeping_top_level(&generate_js_decompression_code(
+        model_config,
+        hash_tables,
+        &mut features_used,
+    )?)?;
+    // The decoder reads it as a string, one character per byte
+    ensure!(source.is_ascii(), "Decompressor source is not ASCII");
+    Ok(source)
+}
+
+/// Decompressor source of the recurrent model, which is large for a model
+const GRU_SRC: &str = include_str!("js_source/gru.js");
+
 pub fn generate_js_decompression_code(
     model_config: &ModelConfig,
+    hash_tables: &HashTableLayouts,
     features_used: &mut ModelRef,
-) -> String {
+) -> Result<String> {
     let mut static_src: String = "".to_owned();
-    let mut out_src = "let model = ".to_owned();
-    out_src += generate_js_ctors(model_config, features_used).as_str();
-
+    let model_src = generate_js_ctors(model_config, hash_tables, features_used)?;
+
+    let mut out_src = "".to_owned();
+    if features_used.contains(ModelRef::HashTable) {
+        for (idx, layout) in hash_tables.values().enumerate() {
+            out_src += &format!(
+                "let hashMap{} = NOrderByteHashMap({}, {});\n",
+                idx, layout.pow2_size, layout.checked as u8
+            );
+        }
+    }
+    out_src += "let model = ";
+    out_src += model_src.as_str();
     out_src += ";\n";
 
     static_src += include_str!("js_source/hash_map.js");
     static_src += include_str!("js_source/coder.js");
     static_src += include_str!("js_source/utils.js");
 
-    if *features_used & (ModelRef::NOrderByte | ModelRef::Word) != ModelRef::None {
+    if *features_used
+        & (ModelRef::NOrderByte
+            | ModelRef::Word
+            | ModelRef::Indirect
+            | ModelRef::Record
+            | ModelRef::Image
+            | ModelRef::Audio
+            | ModelRef::Float32
+            | ModelRef::Column
+            | ModelRef::JsLexer
+            | ModelRef::GlslToken
+            | ModelRef::Number)
+        != ModelRef::None
+    {
         static_src += include_str!("js_source/norder_byte.js");
     }
 
+    if features_used.contains(ModelRef::Indirect) {
+        static_src += include_str!("js_source/indirect.js");
+    }
+
+    if features_used.contains(ModelRef::Record) {
+        static_src += include_str!("js_source/record.js");
+    }
+
+    if features_used.contains(ModelRef::Image) {
+        static_src += include_str!("js_source/image.js");
+    }
+
+    if features_used.contains(ModelRef::Audio) {
+        static_src += include_str!("js_source/audio.js");
+    }
+
+    if features_used.contains(ModelRef::Float32) {
+        static_src += include_str!("js_source/float32.js");
+    }
+
+    if features_used.contains(ModelRef::Column) {
+        static_src += include_str!("js_source/column.js");
+    }
+
+    if features_used.contains(ModelRef::JsLexer) {
+        static_src += include_str!("js_source/js_lexer.js");
+    }
+
+    if features_used.contains(ModelRef::GlslToken) {
+        static_src += include_str!("js_source/glsl_token.js");
+    }
+
+    if features_used.contains(ModelRef::Number) {
+        static_src += include_str!("js_source/number.js");
+    }
+
+    if features_used.contains(ModelRef::Gru) {
+        static_src += GRU_SRC;
+    }
+
+    if features_used.contains(ModelRef::InFile) {
+        static_src += include_str!("js_source/in_range.js");
+    }
+
+    if features_used.contains(ModelRef::BitHistory) {
+        static_src += include_str!("js_source/bit_history.js");
+    }
+
     if features_used.contains(ModelRef::Mixer) {
         static_src += include_str!("js_source/mixer.js");
     }
@@ -59,34 +175,229 @@ pub fn generate_js_decompression_code(
         static_src += include_str!("js_source/adaptive_probability_map.js");
     }
 
-    static_src + "\n" + out_src.as_str()
+    Ok(static_src + "\n" + out_src.as_str())
+}
+
+/// JS variable holding the given hash table
+fn js_hash_map(hash_tables: &HashTableLayouts, table: &Option<String>) -> String {
+    let idx = hash_tables
+        .keys()
+        .position(|name| name == table)
+        .expect("Hash table layout is missing a table used by the model");
+    format!("hashMap{}", idx)
 }
 
-fn generate_js_ctors(model_config: &ModelConfig, features_used: &mut ModelRef) -> String {
-    match model_config {
-        ModelConfig::NOrderByte { byte_mask } => {
+/// Extra JS ctor argument of NOrderByte for the counter type
+fn js_counter_arg(counter: &CounterType, features_used: &mut ModelRef) -> &'static str {
+    match counter {
+        CounterType::Probability => "",
+        CounterType::BitHistory => {
+            *features_used |= ModelRef::BitHistory;
+            ", 1"
+        }
+    }
+}
+
+fn generate_js_ctors(
+    model_config: &ModelConfig,
+    hash_tables: &HashTableLayouts,
+    features_used: &mut ModelRef,
+) -> Result<String> {
+    Ok(match model_config {
+        ModelConfig::NOrderByte {
+            byte_mask,
+            table,
+            counter,
+        } => {
             *features_used |= ModelRef::NOrderByte;
             *features_used |= ModelRef::HashTable;
-            format!("NOrderByte({}, 0)", byte_mask)
+            format!(
+                "NOrderByte({}, 0, {}{})",
+                byte_mask,
+                js_hash_map(hash_tables, table),
+                js_counter_arg(counter, features_used)
+            )
         }
         ModelConfig::Mixer { models } => {
             *features_used |= ModelRef::Mixer;
-            let models_js: Vec<String> = models
-                .into_iter()
-                .map(|c| generate_js_ctors(c, features_used))
-                .collect();
+            let models_js = models
+                .iter()
+                .map(|c| generate_js_ctors(c, hash_tables, features_used))
+                .collect::<Result<Vec<_>>>()?;
             format!("LnMixerPred([{}])", models_js.join(", "))
         }
-        ModelConfig::AdaptiveProbabilityMap(model_config) => {
+        ModelConfig::AdaptiveProbabilityMap { model, stages } => {
             *features_used |= ModelRef::AdaptiveProbabilityMap;
-            let inner_js = generate_js_ctors(model_config, features_used);
-            format!("AdaptiveProbabilityMap(19, {})", inner_js)
+            let inner_js = generate_js_ctors(model, hash_tables, features_used)?;
+            let stages_js: Vec<String> = stages
+                .iter()
+                .map(|stage| {
+                    format!(
+                        "ApmStage({}, {}, {})",
+                        stage.pow2_size, stage.order, stage.rate
+                    )
+                })
+                .collect();
+            format!(
+                "AdaptiveProbabilityMap({}, [{}])",
+                inner_js,
+                stages_js.join(", ")
+            )
         }
-        ModelConfig::Word => {
+        ModelConfig::Word { table, counter } => {
             *features_used |= ModelRef::Word;
-            "NOrderByte(0, 1)".to_string()
+            *features_used |= ModelRef::HashTable;
+            format!(
+                "NOrderByte(0, 1, {}{})",
+                js_hash_map(hash_tables, table),
+                js_counter_arg(counter, features_used)
+            )
         }
-    }
+        ModelConfig::Indirect {
+            order,
+            table,
+            counter,
+        } => {
+            *features_used |= ModelRef::Indirect;
+            *features_used |= ModelRef::HashTable;
+            format!(
+                "Indirect({}, {}{})",
+                order,
+                js_hash_map(hash_tables, table),
+                js_counter_arg(counter, features_used)
+            )
+        }
+        ModelConfig::Record {
+            length,
+            records,
+            table,
+            counter,
+            file_starts,
+        } => {
+            *features_used |= ModelRef::Record;
+            *features_used |= ModelRef::HashTable;
+            // Only a detected length starts over with each file
+            let file_starts = if length.is_none() {
+                file_starts.as_slice()
+            } else {
+                &[]
+            };
+            format!(
+                "Record({}, {}, {:?}, {}{})",
+                length.unwrap_or(0),
+                records,
+                file_starts,
+                js_hash_map(hash_tables, table),
+                js_counter_arg(counter, features_used)
+            )
+        }
+        ModelConfig::Image {
+            width,
+            channels,
+            bytes_per_channel,
+            table,
+            counter,
+        } => {
+            *features_used |= ModelRef::Image;
+            *features_used |= ModelRef::HashTable;
+            format!(
+                "Image({}, {}, {}, {}{})",
+                width,
+                channels,
+                bytes_per_channel,
+                js_hash_map(hash_tables, table),
+                js_counter_arg(counter, features_used)
+            )
+        }
+        ModelConfig::Audio {
+            bits_per_sample,
+            channels,
+            table,
+            counter,
+        } => {
+            *features_used |= ModelRef::Audio;
+            *features_used |= ModelRef::HashTable;
+            format!(
+                "Audio({}, {}, {}{})",
+                bits_per_sample,
+                channels,
+                js_hash_map(hash_tables, table),
+                js_counter_arg(counter, features_used)
+            )
+        }
+        ModelConfig::Float32 {
+            stride,
+            table,
+            counter,
+        } => {
+            *features_used |= ModelRef::Float32;
+            *features_used |= ModelRef::HashTable;
+            format!(
+                "Float32({}, {}{})",
+                stride,
+                js_hash_map(hash_tables, table),
+                js_counter_arg(counter, features_used)
+            )
+        }
+        ModelConfig::Column { table, counter } | ModelConfig::Indentation { table, counter } => {
+            *features_used |= ModelRef::Column;
+            *features_used |= ModelRef::HashTable;
+            format!(
+                "Column({}, {}{})",
+                matches!(model_config, ModelConfig::Indentation { .. }) as u8,
+                js_hash_map(hash_tables, table),
+                js_counter_arg(counter, features_used)
+            )
+        }
+        ModelConfig::JsLexer {
+            order,
+            table,
+            counter,
+        } => {
+            *features_used |= ModelRef::JsLexer;
+            *features_used |= ModelRef::HashTable;
+            format!(
+                "JsLexer({}, {}{})",
+                order,
+                js_hash_map(hash_tables, table),
+                js_counter_arg(counter, features_used)
+            )
+        }
+        ModelConfig::GlslToken { table, counter } => {
+            *features_used |= ModelRef::GlslToken;
+            *features_used |= ModelRef::HashTable;
+            format!(
+                "GlslToken({}{})",
+                js_hash_map(hash_tables, table),
+                js_counter_arg(counter, features_used)
+            )
+        }
+        ModelConfig::Number { table, counter } => {
+            *features_used |= ModelRef::Number;
+            *features_used |= ModelRef::HashTable;
+            format!(
+                "NumberModel({}{})",
+                js_hash_map(hash_tables, table),
+                js_counter_arg(counter, features_used)
+            )
+        }
+        ModelConfig::Gru { cells } => {
+            *features_used |= ModelRef::Gru;
+            format!("Gru({})", cells)
+        }
+        ModelConfig::InFile { file, model, range } => {
+            *features_used |= ModelRef::InFile;
+            let range = range
+                .as_ref()
+                .context(format!("File '{}' of InFile model was not resolved", file))?;
+            format!(
+                "InRange({}, {}, {})",
+                range.start,
+                range.end,
+                generate_js_ctors(model, hash_tables, features_used)?
+            )
+        }
+    })
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug, Clone)]
@@ -98,12 +409,30 @@ pub enum Target {
 pub struct FileWithContent {
     pub path: PathBuf,
     pub content: Vec<u8>,
+    /// If the content is raw deflate data, which the runtime inflates
+    pub deflated: bool,
 }
 
 pub struct BundledFile {
     pub path: PathBuf,
     pub start_offset: u32,
     pub length: u32,
+    /// Set if the file was decoded at build time
+    pub decoded: Option<DecodedFile>,
+}
+
+/// Format of a file decoded at build time, which the runtime exposes
+/// in `wsqz.images` or `wsqz.sounds` instead of `wsqz.files`
+#[derive(Debug, Clone, Copy)]
+pub enum DecodedFile {
+    /// RGBA pixels
+    Image { width: u32, height: u32 },
+    /// Planar samples, see wav_decoder.rs
+    Pcm {
+        sample_rate: u32,
+        channels: u32,
+        bits_per_sample: u32,
+    },
 }
 
 pub fn render_output(
@@ -116,16 +445,41 @@ pub fn render_output(
 ) -> Result<()> {
     debug!("Rendering output with options: {:?}", output_options);
 
+    // Differs from the decoded size when word transforms shrink the input
+    let original_size = js_main_len
+        + bundled_files
+            .iter()
+            .map(|file| file.length as usize)
+            .sum::<usize>();
+
     let OutputGenerationOptions {
         output_dir,
         target,
         model_config,
+        hash_tables,
+        prime_source,
+        dictionary,
+        word_transforms,
+        filters,
     } = output_options;
 
     fs::create_dir_all(&output_dir).context("Failed to create output directory")?;
 
+    let word_transforms_src = js_word_transforms(&word_transforms)?;
+    let filters_src = js_filters(&filters);
+
     let mut features_used = ModelRef::None;
-    let decompression_code = generate_js_decompression_code(&model_config, &mut features_used);
+    let decompression_code =
+        generate_js_decompression_code(&model_config, &hash_tables, &mut features_used)?;
+
+    if features_used.contains(ModelRef::Gru) {
+        let with_gru = deflate_text(&uglify_src(&decompression_code)?)?;
+        let without_gru = deflate_text(&uglify_src(&decompression_code.replacen(GRU_SRC, "", 1))?)?;
+        println!(
+            "Recurrent model adds {} bytes to the decompressor",
+            with_gru.len().saturating_sub(without_gru.len())
+        );
+    }
 
     Ok(match target {
         Target::Web => {
@@ -148,57 +502,137 @@ pub fn render_output(
                 }
 
                 let start_offset = encoded_data.len() + offset;
+                let slice = format!(
+                    "a.slice({},{})",
+                    start_offset,
+                    start_offset + file.content.len()
+                );
                 files_map += &format!(
-                    "\"{}\": a.slice({},{})",
+                    "\"{}\": {}",
                     file.path
                         .file_name()
                         .context("File name")?
                         .to_str()
                         .context("File name to str")?,
-                    start_offset,
-                    start_offset + file.content.len()
+                    if file.deflated {
+                        format!("await inflate({})", slice)
+                    } else {
+                        slice
+                    }
                 );
 
                 offset += file.content.len();
                 compressed_data.extend_from_slice(&file.content);
             }
 
+            let mut images_map = Vec::new();
+            let mut sounds_map = Vec::new();
             for file in &bundled_files {
+                let name = file
+                    .path
+                    .file_name()
+                    .context("File name")?
+                    .to_str()
+                    .context("File name to str")?;
+                let slice = format!(
+                    "d.slice({},{})",
+                    file.start_offset,
+                    file.start_offset + file.length
+                );
+                match file.decoded {
+                    Some(DecodedFile::Image { width, height }) => {
+                        images_map.push(format!(
+                            "\"{}\": new ImageData(new Uint8ClampedArray({}),{},{})",
+                            name, slice, width, height
+                        ));
+                        continue;
+                    }
+                    Some(DecodedFile::Pcm {
+                        sample_rate,
+                        channels,
+                        bits_per_sample,
+                    }) => {
+                        sounds_map.push(format!(
+                            "\"{}\": pcm({},{},{},{})",
+                            name, slice, sample_rate, channels, bits_per_sample
+                        ));
+                        continue;
+                    }
+                    None => {}
+                }
+
                 if is_first {
                     is_first = false;
                 } else {
                     files_map += ", ";
                 }
 
-                files_map += &format!(
-                    "\"{}\": d.slice({},{})",
-                    file.path
-                        .file_name()
-                        .context("File name")?
-                        .to_str()
-                        .context("File name to str")?,
-                    file.start_offset,
-                    file.start_offset + file.length
-                );
+                files_map += &format!("\"{}\": {}", name, slice);
             }
 
             files_map += "}";
-
-            let decompressor_code = Handlebars::new()
-                .render_template(
-                    include_str!("templates/web/boot.js"),
-                    &json!({
-                        "decompressor_source": decompression_code,
-                        "encoded_len": encoded_data.len(),
-                        "decoded_len": size_before_compression,
-                        "files_map": files_map,
-                        "js_main_len": js_main_len,
-                    }),
-                )
-                .context("Failed to render decompression code template")?;
-
-            let decompressor_code_ugly =
-                uglify_src(&decompressor_code).expect("Failed to uglify decompression code");
+            if !images_map.is_empty() {
+                files_map += &format!(", images:{{{}}}", images_map.join(", "));
+            }
+            if !sounds_map.is_empty() {
+                files_map += &format!(", sounds:{{{}}}", sounds_map.join(", "));
+            }
+            let inflate = extra_files.iter().any(|file| file.deflated);
+            let inflate_src = if inflate {
+                include_str!("js_source/inflate.js")
+            } else {
+                ""
+            };
+            let pcm_src = if sounds_map.is_empty() {
+                ""
+            } else {
+                include_str!("js_source/pcm.js")
+            };
+
+            let render_boot = |dictionary: Option<&str>| -> Result<String> {
+                // The inflated boot script is stored in `w` if it starts with the primed source
+                let warm_up = [
+                    prime_source
+                        .as_ref()
+                        .map(|source| format!("w.slice(0,{})", source.len())),
+                    dictionary.map(serde_json::to_string).transpose()?,
+                ]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>()
+                .join("+");
+
+                let decompressor_code = Handlebars::new()
+                    .render_template(
+                        include_str!("templates/web/boot.js"),
+                        &json!({
+                            // A primed decompressor source is already minified and goes first
+                            "decompressor_source": if prime_source.is_some() { "" } else { &decompression_code },
+                            "warm_up": warm_up,
+                            "word_transforms": word_transforms_src,
+                            "filters": filters_src,
+                            "pcm": pcm_src,
+                            "inflate": inflate,
+                            "inflate_src": inflate_src,
+                            // Decode time decides whether the recurrent model pays off
+                            "timed": features_used.contains(ModelRef::Gru),
+                            "encoded_len": encoded_data.len(),
+                            "decoded_len": size_before_compression,
+                            "files_map": files_map,
+                            "js_main_len": js_main_len,
+                        }),
+                    )
+                    .context("Failed to render decompression code template")?;
+
+                let decompressor_code_ugly =
+                    uglify_src(&decompressor_code).expect("Failed to uglify decompression code");
+                Ok(match &prime_source {
+                    Some(source) => format!("{};{}", source, decompressor_code_ugly),
+                    None => decompressor_code_ugly,
+                })
+            };
+
+            let decompressor_code_ugly = render_boot(dictionary.as_deref())?;
 
             info!(
                 "Decompression code size before deflate: {}",
@@ -213,14 +647,34 @@ pub fn render_output(
                 deflated_code.len()
             );
 
-            let html_header_str = Handlebars::new()
-                .render_template(
-                    include_str!("templates/web/index.html"),
-                    &json!({
-                        "decompressor_end": 171 + deflated_code.len(),
-                    }),
-                )
-                .context("Failed to render html header template")?;
+            if dictionary.is_some() {
+                let without_dictionary = deflate_text(&render_boot(None)?)?;
+                println!(
+                    "Priming dictionary costs {} bytes in the deflated bootstrap",
+                    deflated_code.len().saturating_sub(without_dictionary.len())
+                );
+            }
+
+            // The header contains its own length, which settles after a few renders
+            let mut html_header_str = String::new();
+            loop {
+                let header_len = html_header_str.len();
+                let rendered = Handlebars::new()
+                    .render_template(
+                        include_str!("templates/web/index.html"),
+                        &json!({
+                            "decompressor_start": header_len,
+                            "decompressor_end": header_len + deflated_code.len(),
+                            "prime": prime_source.is_some(),
+                        }),
+                    )
+                    .context("Failed to render html header template")?;
+                let settled = rendered.len() == header_len;
+                html_header_str = rendered;
+                if settled {
+                    break;
+                }
+            }
             let html_header_bytes = html_header_str.as_bytes();
 
             info!(
@@ -243,16 +697,16 @@ pub fn render_output(
 
             let final_size = html_header_bytes.len() + deflated_code.len() + compressed_data.len();
 
-            if final_size > size_before_compression {
+            if final_size > original_size {
                 println!(
                     "WARNING: Final size ({}) is larger than original size ({})",
-                    final_size, size_before_compression
+                    final_size, original_size
                 );
             } else {
                 println!(
                     "Generated 'index.html' ({} bytes) with a space saving of {:.2}%",
                     final_size,
-                    100. * (1. - final_size as f64 / size_before_compression as f64)
+                    100. * (1. - final_size as f64 / original_size as f64)
                 );
             }
         }
@@ -264,32 +718,116 @@ pub fn render_output(
             encode_compressed_data(&mut encoded_data_file, &encoded_data)
                 .context("Failed to encode compressed data")?;
 
-            let index_src_path = output_dir.join("index.mjs");
-            let writer =
-                fs::File::create(&index_src_path).expect("Failed to create index.html file");
-
-            let reg = Handlebars::new();
-            reg.render_template_to_write(
-                include_str!("templates/node/index.mjs"),
-                &json!({
-                    "decompressor_source": decompression_code,
-                    "encoded_len": encoded_data.len(),
-                    "decoded_len": size_before_compression,
-                    "input_file": "input.pack",
-                    "output_file": "output.bin",
-                }),
-                writer,
-            )
-            .context("Failed to render node decompressor template")?
+            // The node script is not deflated, so the dictionary costs its quoted length
+            let render_index = |dictionary: Option<&String>| -> Result<String> {
+                let warm_up = [prime_source.as_ref(), dictionary]
+                    .into_iter()
+                    .flatten()
+                    .map(serde_json::to_string)
+                    .collect::<Result<Vec<_>, _>>()?
+                    .join("+");
+
+                Handlebars::new()
+                    .render_template(
+                        include_str!("templates/node/index.mjs"),
+                        &json!({
+                            "decompressor_source": prime_source.as_deref().unwrap_or(&decompression_code),
+                            "warm_up": warm_up,
+                            "word_transforms": word_transforms_src,
+                            "filters": filters_src,
+                            "encoded_len": encoded_data.len(),
+                            "decoded_len": size_before_compression,
+                            "input_file": "input.pack",
+                            "output_file": "output.bin",
+                        }),
+                    )
+                    .context("Failed to render node decompressor template")
+            };
+
+            let index_src = render_index(dictionary.as_ref())?;
+            if dictionary.is_some() {
+                let without_dictionary = render_index(None)?;
+                println!(
+                    "Priming dictionary costs {} bytes in index.mjs",
+                    index_src.len().saturating_sub(without_dictionary.len())
+                );
+            }
+
+            fs::write(output_dir.join("index.mjs"), index_src)
+                .context("Failed to write index.mjs file")?
         }
     })
 }
 
+/// JS that expands the word transformed sections of the decoded data `d`
+fn js_word_transforms(word_transforms: &[(Range<u32>, WordTransform)]) -> Result<String> {
+    if word_transforms.is_empty() {
+        return Ok(String::new());
+    }
+
+    let mut src = include_str!("js_source/word_transform.js").to_owned();
+    // Later sections first, so the ranges of earlier ones stay valid
+    for (range, transform) in word_transforms.iter().rev() {
+        src += &format!(
+            "d = unwords(d, {}, {}, {:?}, {});\n",
+            range.start,
+            range.end,
+            transform.codes,
+            serde_json::to_string(&transform.words.join(" "))?
+        );
+    }
+    Ok(src)
+}
+
+/// JS that undoes the filters of the files in the decoded data `d`, last filter first
+fn js_filters(filters: &[(Range<u32>, Vec<Filter>)]) -> String {
+    let used =
+        |kind: fn(&Filter) -> bool| filters.iter().flat_map(|(_, filters)| filters).any(kind);
+
+    let mut src = String::new();
+    if used(|filter| matches!(filter, Filter::Delta(_))) {
+        src += include_str!("js_source/delta_filter.js");
+    }
+    if used(|filter| matches!(filter, Filter::Transpose(_))) {
+        src += include_str!("js_source/transpose_filter.js");
+    }
+    for (range, filters) in filters {
+        for filter in filters.iter().rev() {
+            src += &match filter {
+                Filter::Delta(distance) => {
+                    format!(
+                        "undelta(d, {}, {}, {});\n",
+                        range.start, range.end, distance
+                    )
+                }
+                Filter::Transpose(stride) => {
+                    format!(
+                        "untranspose(d, {}, {}, {});\n",
+                        range.start, range.end, stride
+                    )
+                }
+            };
+        }
+    }
+    src
+}
+
+/// Deflated size of what the boot script needs to inflate files: `inflate.js` and the async callback
+pub fn inflate_helper_len() -> Result<usize> {
+    let helper = uglify_keeping_top_level(include_str!("js_source/inflate.js"))?;
+    Ok(deflate_text(&format!("{}async ", helper))?.len())
+}
+
 fn deflate_text(text: &str) -> Result<Vec<u8>> {
+    deflate(text.as_bytes())
+}
+
+/// Raw deflate data, as the browser's `DecompressionStream("deflate-raw")` reads it
+pub fn deflate(bytes: &[u8]) -> Result<Vec<u8>> {
     let mut encoded_data = Vec::new();
     let mut writer =
         flate2::write::DeflateEncoder::new(&mut encoded_data, flate2::Compression::best());
-    writer.write_all(text.as_bytes())?;
+    writer.write_all(bytes)?;
     writer.finish()?;
     Ok(encoded_data)
 }
@@ -303,10 +841,19 @@ fn encode_compressed_data<T: Write>(writer: &mut T, encoded_data: &[u8]) -> Resu
 }
 
 fn uglify_src(text: &str) -> Result<String> {
+    uglify(text, &["--toplevel"])
+}
+
+/// Minifies without renaming or dropping top level declarations
+fn uglify_keeping_top_level(text: &str) -> Result<String> {
+    uglify(text, &[])
+}
+
+fn uglify(text: &str, extra_args: &[&str]) -> Result<String> {
     let child = Command::new("uglifyjs")
         .arg("--compress")
         .arg("--mangle")
-        .arg("--toplevel")
+        .args(extra_args)
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .spawn()
@@ -0,0 +1,97 @@
+use anyhow::{Context, Result};
+use png::{ColorType, Decoder, Transformations};
+
+/// Pixels of a decoded PNG, 4 bytes per pixel in RGBA order like `ImageData` expects
+pub struct DecodedImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+/// Decodes a PNG to 8 bit RGBA pixels, expanding palettes, grayscale and missing alpha
+/// and reducing 16 bit channels to 8 bits. Animated PNGs only keep their first frame.
+pub fn decode_png(bytes: &[u8]) -> Result<DecodedImage> {
+    let mut decoder = Decoder::new(bytes);
+    decoder.set_transformations(Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().context("Failed to read PNG header")?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut buf)
+        .context("Failed to decode PNG")?;
+    buf.truncate(info.buffer_size());
+
+    let pixels = match info.color_type {
+        ColorType::Rgba => buf,
+        ColorType::Rgb => buf
+            .chunks_exact(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+            .collect(),
+        ColorType::GrayscaleAlpha => buf
+            .chunks_exact(2)
+            .flat_map(|ga| [ga[0], ga[0], ga[0], ga[1]])
+            .collect(),
+        ColorType::Grayscale => buf.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+        ColorType::Indexed => unreachable!("Palettes are expanded by the decoder"),
+    };
+
+    Ok(DecodedImage {
+        width: info.width,
+        height: info.height,
+        pixels,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decode_png;
+    use png::{BitDepth, ColorType, Encoder};
+
+    fn encode_png(width: u32, color_type: ColorType, bit_depth: BitDepth, data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut encoder = Encoder::new(&mut bytes, width, 2);
+        encoder.set_color(color_type);
+        encoder.set_depth(bit_depth);
+        if color_type == ColorType::Indexed {
+            encoder.set_palette(vec![10, 20, 30, 40, 50, 60]);
+            encoder.set_trns(vec![128]);
+        }
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(data).unwrap();
+        writer.finish().unwrap();
+        bytes
+    }
+
+    #[test]
+    pub fn decodes_to_rgba() {
+        for (color_type, bit_depth, data, expected) in [
+            (
+                ColorType::Grayscale,
+                BitDepth::Eight,
+                vec![0, 9, 200, 255],
+                vec![
+                    0, 0, 0, 255, 9, 9, 9, 255, 200, 200, 200, 255, 255, 255, 255, 255,
+                ],
+            ),
+            (
+                ColorType::Rgb,
+                BitDepth::Sixteen,
+                vec![
+                    1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 0, 0, 0, 0, 0, 0, 255, 255, 0, 0, 0, 0,
+                ],
+                vec![1, 3, 5, 255, 7, 9, 11, 255, 0, 0, 0, 255, 255, 0, 0, 255],
+            ),
+            (
+                ColorType::Indexed,
+                BitDepth::Eight,
+                vec![0, 1, 1, 0],
+                vec![
+                    10, 20, 30, 128, 40, 50, 60, 255, 40, 50, 60, 255, 10, 20, 30, 128,
+                ],
+            ),
+        ] {
+            let image = decode_png(&encode_png(2, color_type, bit_depth, &data)).unwrap();
+            assert_eq!((image.width, image.height), (2, 2));
+            assert_eq!(image.pixels, expected, "{:?}", color_type);
+        }
+    }
+}
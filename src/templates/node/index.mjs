@@ -5,6 +5,11 @@ import { fileURLToPath } from 'url';
 const __dirname = import.meta.dirname;
 
 {{{decompressor_source}}}
+{{#if warm_up}}
+
+// Warm up the model with the same bytes as the encoder
+for (let c of new TextEncoder().encode({{{warm_up}}})) for (let i = 8; i--;) model.pred(), model.learn(c >> i & 1);
+{{/if}}
 
 fs.readFile(__dirname + '/{{{input_file}}}', (err, data) => {
     if (err) {
@@ -12,6 +17,11 @@ fs.readFile(__dirname + '/{{{input_file}}}', (err, data) => {
         return;
     }
 
-    fs.writeFileSync(__dirname + '/{{{output_file}}}', decompress(model, new Uint8Array(data.buffer), {{{encoded_len}}}, {{{decoded_len}}}));
+    let start = performance.now();
+    let d = decompress(model, new Uint8Array(data.buffer), {{{encoded_len}}}, {{{decoded_len}}});
+    {{{word_transforms}}}
+    {{{filters}}}
+    console.log(`Decompressed {{{decoded_len}}} bytes in ${Math.round(performance.now() - start)} ms`);
+    fs.writeFileSync(__dirname + '/{{{output_file}}}', d);
 });
 
@@ -1,9 +1,20 @@
 {{{decompressor_source}}}
 
 document.body.innerHTML = "";
-p.slice(o).arrayBuffer().then(b => {
+{{#if warm_up}}
+// Warm up with the same bytes as the encoder: the start of the inflated
+// boot script if it was primed with it, then the priming dictionary
+for (c of new TextEncoder().encode({{{warm_up}}})) for (i = 8; i--;) model.pred(), model.learn(c >> i & 1);
+{{/if}}
+{{{inflate_src}}}
+p.slice(o).arrayBuffer().then({{#if inflate}}async {{/if}}b => {
     a = new Uint8Array(b);
+    {{#if timed}}t = performance.now();{{/if}}
     d = decompress(model, a, {{{encoded_len}}}, {{{decoded_len}}});
+    {{{word_transforms}}}
+    {{{filters}}}
+    {{#if timed}}console.log(`Decompressed {{{decoded_len}}} bytes in ${Math.round(performance.now() - t)} ms`);{{/if}}
+    {{{pcm}}}
     wsqz = {
         {{{files_map}}}
     };
@@ -0,0 +1,151 @@
+use anyhow::{bail, ensure, Context, Result};
+
+/// PCM samples of a decoded WAV file, stored planar: all samples of the first channel,
+/// then all of the second... 8 bit samples are unsigned, 16 bit samples signed little-endian.
+pub struct DecodedWav {
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub bits_per_sample: u32,
+    pub samples: Vec<u8>,
+}
+
+const WAVE_FORMAT_PCM: u16 = 1;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xfffe;
+
+fn u16_at(bytes: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([bytes[pos], bytes[pos + 1]])
+}
+
+fn u32_at(bytes: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(bytes[pos..pos + 4].try_into().unwrap())
+}
+
+/// Parses the RIFF chunks of an 8 or 16 bit PCM WAV file and deinterleaves its samples.
+/// Trailing bytes of an incomplete sample frame are dropped.
+pub fn decode_wav(bytes: &[u8]) -> Result<DecodedWav> {
+    ensure!(
+        bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WAVE",
+        "Not a RIFF WAVE file"
+    );
+
+    let mut format = None;
+    let mut data = None;
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let id = &bytes[pos..pos + 4];
+        let len = u32_at(bytes, pos + 4) as usize;
+        let body = &bytes[pos + 8..(pos + 8 + len).min(bytes.len())];
+        match id {
+            b"fmt " => {
+                ensure!(body.len() >= 16, "Format chunk is too short");
+                let mut tag = u16_at(body, 0);
+                if tag == WAVE_FORMAT_EXTENSIBLE {
+                    ensure!(body.len() >= 26, "Extensible format chunk is too short");
+                    // The sub format GUID starts with the format tag
+                    tag = u16_at(body, 24);
+                }
+                ensure!(
+                    tag == WAVE_FORMAT_PCM,
+                    "Only PCM WAV files are supported, not format {}",
+                    tag
+                );
+                format = Some((
+                    u16_at(body, 2) as u32,
+                    u32_at(body, 4),
+                    u16_at(body, 14) as u32,
+                ));
+            }
+            b"data" => data = Some(body),
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        pos += 8 + len + (len & 1);
+    }
+
+    let (channels, sample_rate, bits_per_sample) = format.context("Missing format chunk")?;
+    let data = data.context("Missing data chunk")?;
+    ensure!(channels > 0, "WAV file has no channels");
+    if bits_per_sample != 8 && bits_per_sample != 16 {
+        bail!(
+            "Only 8 and 16 bit WAV files are supported, not {} bit",
+            bits_per_sample
+        );
+    }
+
+    let sample_len = bits_per_sample as usize / 8;
+    let frame_len = sample_len * channels as usize;
+    let frames = data.len() / frame_len;
+    let samples = (0..channels as usize)
+        .flat_map(|channel| {
+            (0..frames).flat_map(move |frame| {
+                let start = frame * frame_len + channel * sample_len;
+                &data[start..start + sample_len]
+            })
+        })
+        .copied()
+        .collect();
+
+    Ok(DecodedWav {
+        sample_rate,
+        channels,
+        bits_per_sample,
+        samples,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decode_wav;
+
+    fn wav(format: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut bytes = b"RIFF\0\0\0\0WAVE".to_vec();
+        // An odd length chunk before the format, which is padded
+        bytes.extend_from_slice(b"LIST\x03\0\0\0abc\0");
+        bytes.extend_from_slice(b"fmt ");
+        bytes.extend_from_slice(&(format.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(format);
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    fn pcm_format(tag: u16, channels: u16, bits_per_sample: u16) -> Vec<u8> {
+        let block_align = channels * bits_per_sample / 8;
+        [
+            &tag.to_le_bytes()[..],
+            &channels.to_le_bytes(),
+            &22050u32.to_le_bytes(),
+            &(22050 * block_align as u32).to_le_bytes(),
+            &block_align.to_le_bytes(),
+            &bits_per_sample.to_le_bytes(),
+        ]
+        .concat()
+    }
+
+    #[test]
+    pub fn deinterleaves() {
+        // Two 16 bit stereo frames and an incomplete one
+        let data = [1, 2, 3, 4, 5, 6, 7, 8, 9];
+        let decoded = decode_wav(&wav(&pcm_format(1, 2, 16), &data)).unwrap();
+        assert_eq!(
+            (
+                decoded.sample_rate,
+                decoded.channels,
+                decoded.bits_per_sample
+            ),
+            (22050, 2, 16)
+        );
+        assert_eq!(decoded.samples, [1, 2, 5, 6, 3, 4, 7, 8]);
+
+        // 8 bit mono in the extensible format with the PCM sub format
+        let mut format = pcm_format(0xfffe, 1, 8);
+        format.extend_from_slice(&[22, 0, 8, 0, 4, 0, 0, 0, 1, 0]);
+        format.extend_from_slice(&[0; 14]);
+        let decoded = decode_wav(&wav(&format, &[10, 20, 30])).unwrap();
+        assert_eq!(decoded.samples, [10, 20, 30]);
+
+        assert!(decode_wav(&wav(&pcm_format(1, 1, 24), &[0; 6])).is_err());
+        assert!(decode_wav(&wav(&pcm_format(3, 1, 16), &[0; 4])).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+
+/// Shortest word worth replacing
+const MIN_WORD_LENGTH: usize = 4;
+
+/// Cost of a dictionary entry relative to a replaced occurrence.
+/// Context mixing already codes repeated words cheaply, so only words
+/// that occur very often gain more than their dictionary entry costs.
+const DICTIONARY_WEIGHT: i64 = 16;
+
+/// Reversible text transform that replaces frequent long words (identifiers and keywords)
+/// with single byte codes that don't occur in the text.
+/// The JS decoder expands them again with `unwords` in js_source/word_transform.js.
+#[derive(Debug, Clone)]
+pub struct WordTransform {
+    /// Code of each word, bytes that are unused in the original text
+    pub codes: Vec<u8>,
+    pub words: Vec<String>,
+}
+
+fn is_word_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'$'
+}
+
+/// Splits text into words and the bytes between them
+fn split_words(text: &[u8]) -> impl Iterator<Item = &[u8]> {
+    text.chunk_by(|a, b| is_word_byte(*a) && is_word_byte(*b))
+}
+
+impl WordTransform {
+    /// Builds a dictionary of the words of `text` that save the most bytes when replaced.
+    /// None if no word pays off or all byte values are used.
+    pub fn build(text: &[u8]) -> Option<Self> {
+        let mut used = [false; 256];
+        for &byte in text {
+            used[byte as usize] = true;
+        }
+        let free_codes: Vec<u8> = (1..=255u8).filter(|&code| !used[code as usize]).collect();
+
+        let mut counts = BTreeMap::<&[u8], usize>::new();
+        for word in split_words(text) {
+            if word.len() >= MIN_WORD_LENGTH && is_word_byte(word[0]) {
+                *counts.entry(word).or_default() += 1;
+            }
+        }
+
+        // Bytes saved in the text minus the weighted bytes the word takes in the dictionary
+        let mut scored: Vec<(i64, &[u8])> = counts
+            .into_iter()
+            .map(|(word, count)| {
+                let len = word.len() as i64;
+                (
+                    count as i64 * (len - 1) - DICTIONARY_WEIGHT * (len + 4),
+                    word,
+                )
+            })
+            .filter(|(score, _)| *score > 0)
+            .collect();
+        // Highest score first, ties in word order so the dictionary is deterministic
+        scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(b.1)));
+        scored.truncate(free_codes.len());
+
+        if scored.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            codes: free_codes[..scored.len()].to_vec(),
+            words: scored
+                .into_iter()
+                .map(|(_, word)| String::from_utf8_lossy(word).into_owned())
+                .collect(),
+        })
+    }
+
+    /// Replaces the dictionary words in `text` with their codes
+    pub fn apply(&self, text: &[u8]) -> Vec<u8> {
+        let codes: BTreeMap<&[u8], u8> = self
+            .words
+            .iter()
+            .map(|word| word.as_bytes())
+            .zip(self.codes.iter().copied())
+            .collect();
+
+        let mut out = Vec::with_capacity(text.len());
+        for word in split_words(text) {
+            match codes.get(word) {
+                Some(&code) => out.push(code),
+                None => out.extend_from_slice(word),
+            }
+        }
+        out
+    }
+
+    /// Expands the codes in `data` to their words, like the JS decoder
+    #[cfg(test)]
+    pub fn invert(&self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len());
+        for &byte in data {
+            match self.codes.iter().position(|&code| code == byte) {
+                Some(idx) => out.extend_from_slice(self.words[idx].as_bytes()),
+                None => out.push(byte),
+            }
+        }
+        out
+    }
+
+    /// Number of bytes the dictionary takes in the decoder
+    pub fn dictionary_len(&self) -> usize {
+        self.words.iter().map(|word| word.len() + 1).sum::<usize>() + 4 * self.codes.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WordTransform;
+
+    #[test]
+    pub fn round_trip() {
+        let text = std::fs::read("tests/ray_tracer/index.js").unwrap();
+        let transform = WordTransform::build(&text).unwrap();
+        let transformed = transform.apply(&text);
+
+        assert!(transformed.len() < text.len());
+        assert_eq!(transform.invert(&transformed), text);
+    }
+
+    #[test]
+    pub fn no_free_codes() {
+        let text: Vec<u8> = (0..=255u8).chain(*b" function function function").collect();
+        assert!(WordTransform::build(&text).is_none());
+    }
+}